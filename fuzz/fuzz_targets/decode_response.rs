@@ -0,0 +1,10 @@
+// Jackson Coxson
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// The base64 body as received from the server, through the protobuf and parquet decoding
+fuzz_target!(|body: &str| {
+    let _ = moabdb::decode_response(body);
+});
@@ -0,0 +1,10 @@
+// Jackson Coxson
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// The protobuf response, skipping base64, so the fuzzer reaches the parquet reader more often
+fuzz_target!(|response: &[u8]| {
+    let _ = moabdb::decode_response_bytes(response);
+});
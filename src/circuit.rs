@@ -0,0 +1,159 @@
+// Jackson Coxson
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Stops requests to the API after repeated failures, see `Client::with_circuit_breaker`
+pub(crate) struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Consecutive failures since the last success
+    failures: u32,
+    /// When the circuit last opened, None while it's closed
+    opened_at: Option<Instant>,
+    /// Whether a probe request is in flight after the cooldown
+    probing: bool,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// A permit to make a request now, or None while the circuit is open.
+    /// Once the cooldown has passed a single probe request is allowed through, and the
+    /// circuit stays open for everything else until that probe's result is recorded.
+    /// A probe permit dropped without a result, such as when the request panics, lets the
+    /// next request probe instead.
+    pub(crate) fn allow(&self) -> Option<Permit<'_>> {
+        let mut state = self.lock();
+        let probe = match state.opened_at {
+            None => false,
+            Some(opened_at) if !state.probing && opened_at.elapsed() >= self.cooldown => {
+                state.probing = true;
+                true
+            }
+            Some(_) => return None,
+        };
+        Some(Permit {
+            circuit: self,
+            probe,
+            recorded: false,
+        })
+    }
+
+    fn record(&self, failed: bool) {
+        let mut state = self.lock();
+        if !failed {
+            *state = State::default();
+            return;
+        }
+        state.failures = state.failures.saturating_add(1);
+        // A failed probe restarts the cooldown
+        if state.probing || state.failures >= self.threshold {
+            state.opened_at = Some(Instant::now());
+            state.probing = false;
+        }
+    }
+
+    /// Whether the circuit is open, including while a probe is in flight
+    pub(crate) fn is_open(&self) -> bool {
+        self.lock().opened_at.is_some()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(e) => e.into_inner(),
+        }
+    }
+}
+
+/// Lets one request through a `CircuitBreaker`, see `CircuitBreaker::allow`
+pub(crate) struct Permit<'a> {
+    circuit: &'a CircuitBreaker,
+    probe: bool,
+    recorded: bool,
+}
+
+impl Permit<'_> {
+    /// Record the result of the request this permit let through
+    pub(crate) fn record(mut self, failed: bool) {
+        self.recorded = true;
+        self.circuit.record(failed);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.probe && !self.recorded {
+            self.circuit.lock().probing = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(circuit: &CircuitBreaker, failed: bool) {
+        circuit.allow().unwrap().record(failed);
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let circuit = CircuitBreaker::new(2, Duration::from_secs(60));
+        request(&circuit, true);
+        request(&circuit, true);
+        assert!(circuit.is_open());
+        assert!(circuit.allow().is_none());
+    }
+
+    #[test]
+    fn success_resets_the_count() {
+        let circuit = CircuitBreaker::new(2, Duration::from_secs(60));
+        request(&circuit, true);
+        request(&circuit, false);
+        request(&circuit, true);
+        assert!(!circuit.is_open());
+    }
+
+    #[test]
+    fn half_open_allows_one_probe() {
+        let circuit = CircuitBreaker::new(1, Duration::ZERO);
+        request(&circuit, true);
+        let probe = circuit.allow().unwrap();
+        assert!(circuit.allow().is_none());
+        probe.record(true);
+        let probe = circuit.allow().unwrap();
+        probe.record(false);
+        assert!(!circuit.is_open());
+        assert!(circuit.allow().is_some());
+    }
+
+    #[test]
+    fn panicking_probe_releases_the_circuit() {
+        let circuit = CircuitBreaker::new(1, Duration::ZERO);
+        request(&circuit, true);
+        let panicked = std::panic::catch_unwind(|| {
+            let _probe = circuit.allow().unwrap();
+            panic!("decoding failed");
+        });
+        assert!(panicked.is_err());
+        assert!(circuit.is_open());
+        let probe = circuit.allow().unwrap();
+        probe.record(false);
+        assert!(!circuit.is_open());
+    }
+}
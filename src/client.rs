@@ -0,0 +1,2236 @@
+// Jackson Coxson
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::Sender,
+        Mutex, RwLock,
+    },
+    time::Instant,
+};
+
+use chrono::NaiveDateTime;
+use polars::prelude::{DataFrame, ParquetReader, SerReader};
+
+use crate::{
+    circuit::CircuitBreaker,
+    coalesce::Coalescer,
+    credentials::Credentials,
+    errors::{MoabError, TimeoutPhase},
+    frame::{self, Aggregation, Coverage, OutputSpec, PrecisionMode},
+    interceptor::{Interceptor, OutgoingRequest, ResponseEvent},
+    metrics::{Metrics, RequestEvent},
+    protocol, tag,
+    throttle::Throttle,
+    window::{Market, Session, Window, WindowBuilder, WindowLength},
+    API_URL,
+};
+
+/// The most redirects followed before a request fails with `MoabError::TooManyRedirects`
+const MAX_REDIRECTS: u32 = 5;
+
+/// The most requests `get_equities` has in flight at once
+pub const BATCH_CONCURRENCY: usize = 8;
+
+/// The status code of a response during scheduled maintenance, at the HTTP or protocol level
+const MAINTENANCE_CODE: u32 = 503;
+
+/// The most bytes allocated for a response body from its `Content-Length` before reading it.
+/// Larger bodies grow the buffer as they're read.
+const MAX_PREALLOCATED_BYTES: usize = 256 * 1024 * 1024;
+
+/// The most bytes of an unexpected response body kept in `MoabError::UnexpectedBody`
+const BODY_SNIPPET_BYTES: usize = 256;
+
+/// The start of the window `get_equity_full_history` requests, 1900-01-01 UTC,
+/// earlier than any data the server holds
+const FULL_HISTORY_START: i64 = -2_208_988_800;
+
+/// The fewest intraday bars assumed to be in a trading day when sizing `get_equity_last_n`'s window
+const INTRADAY_BARS_PER_DAY: usize = 390;
+
+/// The most a `poll_latest` tick is delayed by, as a fraction of the interval, so many
+/// clients polling on the same interval don't all hit the API at once
+const POLL_JITTER: f64 = 0.1;
+
+/// The fraction of a window's estimated bars a frame holds below which
+/// `Client::with_short_frame_retries` retries it, unless set with `Client::with_short_frame_threshold`
+pub const DEFAULT_SHORT_FRAME_THRESHOLD: f64 = 0.5;
+
+/// How recently a window must end for `Client::with_short_frame_retries` to retry it,
+/// as older data has had time to be materialized
+const RECENT_DATA_HOURS: i64 = 24;
+
+/// The most idle connections the client keeps open, unless set with `Client::with_pool_size`.
+/// Every request goes to the same host, so this is the per host limit too.
+pub const DEFAULT_POOL_SIZE: usize = BATCH_CONCURRENCY;
+
+/// Identifies identical requests: the symbol, datatype and the timestamps sent to the server
+type RequestKey = (String, String, i64, i64);
+
+/// Supplies fresh credentials when a request is rejected as unauthorized
+type CredentialsProvider = Box<dyn Fn() -> Option<Credentials> + Send + Sync>;
+
+/// Reads a response body into the status code it carried and the caller's output
+type BodyReader<'a, T> = dyn Fn(Vec<u8>) -> (Option<u32>, Result<T, MoabError>) + 'a;
+
+/// A reusable MoabDB client.
+/// The client holds one connection pool and the credentials used for every request it makes,
+/// so repeated requests don't pay for a new TLS handshake each time.
+/// Identical requests made from several threads at once are only sent once, and every
+/// caller gets a copy of the result.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{client::Client, window::WindowBuilder, window::WindowLength};
+///
+/// let client = Client::new(None);
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Months(3))
+///     .build()
+///     .unwrap();
+///
+/// let df = client.get_equity("AAPL", window, false).unwrap();
+/// println!("{:?}", df);
+/// ```
+pub struct Client {
+    agent: ureq::Agent,
+    agent_config: AgentConfig,
+    overall_timeout: Option<std::time::Duration>,
+    credentials: RwLock<Option<Credentials>>,
+    credentials_provider: Option<CredentialsProvider>,
+    metrics: Option<Box<dyn Metrics>>,
+    interceptors: Vec<Box<dyn Interceptor>>,
+    sorted_output: bool,
+    expected_columns: Option<Vec<String>>,
+    tag_symbol: bool,
+    symbol_column: String,
+    canonical_columns: bool,
+    circuit: Option<CircuitBreaker>,
+    precision: Option<PrecisionMode>,
+    max_intraday_lookback: Option<chrono::Duration>,
+    clamp_intraday: bool,
+    fallback_unauthenticated: bool,
+    require_credentials: bool,
+    intraday_fallback: bool,
+    output: Option<OutputSpec>,
+    price_scale: Option<f64>,
+    row_limit: Option<usize>,
+    time_column: Option<String>,
+    session: Option<(Session, Market)>,
+    throttle: Option<Throttle>,
+    short_frame_retries: Option<(u32, std::time::Duration)>,
+    short_frame_threshold: f64,
+    in_flight: Coalescer<RequestKey, Result<DataFrame, MoabError>>,
+    max_clock_skew: Option<chrono::Duration>,
+    clock_skew: Mutex<Option<chrono::Duration>>,
+    /// Dropped with the client to stop the keep-alive thread
+    #[cfg(feature = "keep-alive")]
+    keep_alive: Option<std::sync::mpsc::Sender<()>>,
+}
+
+impl Client {
+    /// Create a new client
+    /// # Arguments
+    /// * `credentials` - The credentials to use to authenticate requests. If None, requests will be unauthenticated
+    pub fn new(credentials: Option<Credentials>) -> Self {
+        Self {
+            agent: AgentConfig::default().build(),
+            agent_config: AgentConfig::default(),
+            overall_timeout: None,
+            credentials: RwLock::new(credentials),
+            credentials_provider: None,
+            metrics: None,
+            interceptors: Vec::new(),
+            sorted_output: false,
+            expected_columns: None,
+            tag_symbol: false,
+            symbol_column: frame::SYMBOL_COLUMN.to_string(),
+            canonical_columns: false,
+            circuit: None,
+            precision: None,
+            max_intraday_lookback: None,
+            clamp_intraday: false,
+            fallback_unauthenticated: false,
+            require_credentials: false,
+            intraday_fallback: false,
+            output: None,
+            price_scale: None,
+            row_limit: None,
+            time_column: None,
+            session: None,
+            throttle: None,
+            short_frame_retries: None,
+            short_frame_threshold: DEFAULT_SHORT_FRAME_THRESHOLD,
+            in_flight: Coalescer::new(),
+            max_clock_skew: None,
+            clock_skew: Mutex::new(None),
+            #[cfg(feature = "keep-alive")]
+            keep_alive: None,
+        }
+    }
+
+    /// Replace the credentials used for every following request.
+    /// Requests already in flight keep the credentials they started with.
+    pub fn set_credentials(&self, credentials: Option<Credentials>) {
+        if let Ok(mut current) = self.credentials.write() {
+            *current = credentials;
+        }
+    }
+
+    /// Fetch new credentials from `provider` whenever a request is rejected as unauthorized,
+    /// then retry that request once with them. Use this for tokens that rotate.
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::{client::Client, credentials::Credentials};
+    ///
+    /// let client = Client::new(None).with_credentials_provider(|| {
+    ///     std::env::var("MOABDB_CREDENTIALS").ok()?.parse::<Credentials>().ok()
+    /// });
+    /// ```
+    pub fn with_credentials_provider(
+        mut self,
+        provider: impl Fn() -> Option<Credentials> + Send + Sync + 'static,
+    ) -> Self {
+        self.credentials_provider = Some(Box::new(provider));
+        self
+    }
+
+    /// Retry a request without credentials when the server rejects the client's credentials,
+    /// such as when the account's quota is exhausted, instead of failing. The server doesn't say why
+    /// credentials were rejected, so this happens on every `MoabError::Unauthorized` after any
+    /// credentials provider has had its chance. Unauthenticated data may be delayed or cover less
+    /// history than the account's, so the retry is flagged as `degraded` in its metrics event.
+    /// Requests aren't retried by default.
+    pub fn with_fallback_unauthenticated(mut self, fallback: bool) -> Self {
+        self.fallback_unauthenticated = fallback;
+        self
+    }
+
+    /// Fail with `MoabError::AuthenticationRequired` instead of sending any request without
+    /// credentials, so credentials that were expected but missing, or empty, fail loudly rather than
+    /// quietly returning the more limited unauthenticated data. A credentials provider is still
+    /// asked for credentials first, and `with_fallback_unauthenticated` is ignored.
+    /// This includes requests made with `get_equity_as`. Unauthenticated requests are allowed by default.
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::{client::Client, credentials::Credentials};
+    ///
+    /// let credentials = Credentials::new("username", "token");
+    /// let client = Client::new(Some(credentials)).require_credentials(true);
+    /// ```
+    pub fn require_credentials(mut self, require: bool) -> Self {
+        self.require_credentials = require;
+        self
+    }
+
+    /// Fall back to daily data when an intraday equity request finds nothing for the ticker,
+    /// for symbols with daily history but no intraday bars. The same window is requested as
+    /// daily data, unclamped by `with_max_intraday_lookback`, exactly when the intraday request
+    /// fails with `MoabError::NotFound` or returns a frame without rows. Any other error, such as
+    /// a transport error or rejected credentials, is returned as it is. The daily request is flagged
+    /// as `degraded` in its metrics event. Only `get_equity` and the methods built on it
+    /// fall back; `get_data` requests exactly the datatype it's given. There's no fallback by default.
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::client::Client;
+    ///
+    /// let client = Client::new(None).with_intraday_fallback(true);
+    /// ```
+    pub fn with_intraday_fallback(mut self, fallback: bool) -> Self {
+        self.intraday_fallback = fallback;
+        self
+    }
+
+    /// Retry an equity request up to `retries` times, waiting `delay` before each, while it returns
+    /// suspiciously few rows, for the latest bars that the server hasn't materialized yet.
+    /// A frame is suspiciously short when it holds fewer rows than `with_short_frame_threshold`
+    /// times the bars expected up to now, see `Window::estimate_rows`. Only windows ending within
+    /// the last day are retried, so older gaps such as holidays or halts don't slow every request,
+    /// and neither are frames cut down by `with_row_limit` or intraday frames cut down by a
+    /// session other than `Session::Regular`. The last frame received is returned even if it's still short.
+    /// Requests aren't retried by default.
+    /// # Examples
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use moabdb::client::Client;
+    ///
+    /// let client = Client::new(None).with_short_frame_retries(3, Duration::from_millis(500));
+    /// ```
+    pub fn with_short_frame_retries(mut self, retries: u32, delay: std::time::Duration) -> Self {
+        self.short_frame_retries = Some((retries, delay));
+        self
+    }
+
+    /// The fraction of the expected bars below which `with_short_frame_retries` retries a frame,
+    /// `DEFAULT_SHORT_FRAME_THRESHOLD` by default. The estimate doesn't know about holidays or
+    /// early closes, so a threshold close to 1 retries complete frames on those days too.
+    pub fn with_short_frame_threshold(mut self, fraction: f64) -> Self {
+        self.short_frame_threshold = fraction;
+        self
+    }
+
+    /// Record an event with the given metrics hook for every request the client makes.
+    /// No metrics are recorded by default.
+    ///
+    /// With the `otel` feature every request also gets an OpenTelemetry client span from the global
+    /// tracer provider, a child of the caller's current context, and the trace context is sent to
+    /// the API in the headers written by the global propagator. The default propagator writes none,
+    /// so install a W3C `TraceContextPropagator` to send `traceparent`.
+    pub fn with_metrics(mut self, metrics: impl Metrics + 'static) -> Self {
+        self.metrics = Some(Box::new(metrics));
+        self
+    }
+
+    /// Call `interceptor` before every request the client sends and after its response.
+    /// Interceptors are called in the order they were added.
+    pub fn with_interceptor(mut self, interceptor: impl Interceptor + 'static) -> Self {
+        self.interceptors.push(Box::new(interceptor));
+        self
+    }
+
+    /// Sort every returned frame by its timestamp column and drop exact duplicate rows,
+    /// see `frame::sort_and_dedup`. Frames are returned in the server's order by default.
+    pub fn with_sorted_output(mut self, sorted: bool) -> Self {
+        self.sorted_output = sorted;
+        self
+    }
+
+    /// Add a `symbol` column to every returned frame that doesn't already have one, see
+    /// `frame::tag_symbol`, so batch results can be concatenated. Frames aren't tagged by default.
+    /// The column can be renamed with `with_symbol_column`.
+    pub fn with_symbol_tag(mut self, tag: bool) -> Self {
+        self.tag_symbol = tag;
+        self
+    }
+
+    /// Tag every returned frame with its symbol in a column named `name` instead of `symbol`,
+    /// for downstream tools that reserve `symbol` or frames that already have a column by that name.
+    /// A frame that already has a column named `name` is left as it is, so pick a name the data
+    /// doesn't use. This turns on `with_symbol_tag`, and `correlation_matrix` labels its rows
+    /// with the same name.
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::client::Client;
+    ///
+    /// let client = Client::new(None).with_symbol_column("ticker");
+    /// ```
+    pub fn with_symbol_column(mut self, name: impl AsRef<str>) -> Self {
+        self.symbol_column = name.as_ref().to_string();
+        self.tag_symbol = true;
+        self
+    }
+
+    /// Rename the columns of every returned frame to the canonical names in
+    /// `frame::CANONICAL_COLUMNS`, see `frame::canonicalize_columns`. This happens before any
+    /// other output option, so expected columns can be given by their canonical names.
+    /// Columns keep the server's names by default.
+    pub fn with_canonical_columns(mut self, canonical: bool) -> Self {
+        self.canonical_columns = canonical;
+        self
+    }
+
+    /// Divide integer price columns by `scale`, for feeds that return prices as scaled integers,
+    /// such as 100 for cents, see `frame::scale_prices`. Price columns that are already decimal are
+    /// left alone. The server is assumed to return decimal dollars, so prices aren't scaled by default.
+    pub fn with_price_scale(mut self, scale: f64) -> Self {
+        self.price_scale = Some(scale);
+        self
+    }
+
+    /// Cast the float columns of every returned frame to the float type of `precision`,
+    /// see `frame::with_precision`. `PrecisionMode::F32` halves the memory of price columns at the
+    /// cost of precision past about 7 significant digits. `PrecisionMode::Fixed` returns prices as
+    /// exact scaled integers instead, such as cents, for totals that mustn't drift, in columns
+    /// renamed with their scale, such as `close_e2`.
+    /// Frames keep the server's types by default.
+    pub fn with_precision(mut self, precision: PrecisionMode) -> Self {
+        self.precision = Some(precision);
+        self
+    }
+
+    /// Reorder the columns and rows of every returned frame as set by `spec`, see
+    /// `frame::apply_output`, to make output deterministic. This is applied after every other
+    /// output option, so its sort order wins over `with_sorted_output`.
+    /// Frames keep the server's order by default.
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::{client::Client, frame::{OutputSpec, SortOrder}};
+    ///
+    /// let client = Client::new(None).with_output(
+    ///     OutputSpec::new()
+    ///         .columns(&["timestamp", "open", "high", "low", "close"])
+    ///         .sort(SortOrder::Descending),
+    /// );
+    /// ```
+    pub fn with_output(mut self, spec: OutputSpec) -> Self {
+        self.output = Some(spec);
+        self
+    }
+
+    /// Keep only the first `limit` rows of every returned frame, such as for a preview.
+    /// The protocol has no row limit, so the server still sends the whole window and this only
+    /// saves memory after decoding. Use a shorter window to transfer less. The rows kept are the first
+    /// after every other output option, so with `with_output` sorting descending they're the newest.
+    /// Frames aren't limited by default.
+    pub fn with_row_limit(mut self, limit: usize) -> Self {
+        self.row_limit = Some(limit);
+        self
+    }
+
+    /// Use `name` as the time key of every returned frame, converting it to a `Datetime` column
+    /// sorted ascending and flagged as sorted, see `frame::to_time_index`, so polars' time based
+    /// operations work without a manual cast and sort. The server's `timestamp` column, or a column
+    /// `frame::canonicalize_columns` would rename to it, is renamed to `name` first. The other time
+    /// based output options sort by this column too. By default the server's `timestamp` column is
+    /// returned as epoch seconds.
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::client::Client;
+    ///
+    /// let client = Client::new(None).with_time_column("time");
+    /// ```
+    pub fn with_time_column(mut self, name: impl AsRef<str>) -> Self {
+        self.time_column = Some(name.as_ref().to_string());
+        self
+    }
+
+    /// Keep only the intraday bars that start in `session` of `market`, such as just the pre-market
+    /// bars for gap analysis, see `frame::filter_session`. The server has no session filter, so the
+    /// bars are filtered after they're received, in the market's local time accounting for daylight
+    /// saving time. Daily data is returned as it is. Every bar is kept by default.
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::{client::Client, window::{Market, Session}};
+    ///
+    /// let client = Client::new(None).with_session(Session::PreMarket, Market::Nyse);
+    /// ```
+    pub fn with_session(mut self, session: Session, market: Market) -> Self {
+        self.session = Some((session, market));
+        self
+    }
+
+    /// Fail requests with `MoabError::DecodeError` when the returned frame is missing any of
+    /// `columns`, see `frame::check_columns`. By default equity frames are checked for
+    /// `frame::EQUITY_COLUMNS` and other datatypes aren't checked; this replaces that check for
+    /// every datatype, for a server whose schema has moved on. An empty list turns checking off.
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::client::Client;
+    ///
+    /// let client = Client::new(None).with_expected_columns(&["timestamp", "close"]);
+    /// ```
+    pub fn with_expected_columns(mut self, columns: &[impl AsRef<str>]) -> Self {
+        self.expected_columns = Some(columns.iter().map(|c| c.as_ref().to_string()).collect());
+        self
+    }
+
+    /// Limit how far back intraday requests from `get_equity` may start, for a server that only
+    /// keeps recent intraday data. A window starting more than `lookback` before now fails with
+    /// `MoabError::RequestError` before any request is made, or if `clamp` is set has its start moved
+    /// up to the limit, failing only if the whole window is older than that. Daily requests are
+    /// unaffected. Intraday windows aren't limited by default.
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::client::Client;
+    ///
+    /// let client = Client::new(None).with_max_intraday_lookback(chrono::Duration::days(60), true);
+    /// ```
+    pub fn with_max_intraday_lookback(mut self, lookback: chrono::Duration, clamp: bool) -> Self {
+        self.max_intraday_lookback = Some(lookback);
+        self.clamp_intraday = clamp;
+        self
+    }
+
+    /// Fail requests with `MoabError::ClockSkew` when the local clock differs from the
+    /// server's `Date` header by more than `max_skew`. Skew is measured but not checked by default.
+    pub fn with_max_clock_skew(mut self, max_skew: chrono::Duration) -> Self {
+        self.max_clock_skew = Some(max_skew);
+        self
+    }
+
+    /// The difference between the server's clock and the local clock seen on the most recent
+    /// response, positive when the server is ahead. None until a response with a `Date` header is received.
+    /// `WindowBuilder` anchors windows with no start or end at the local time, so this can be
+    /// added to those anchors to correct for a skewed local clock.
+    pub fn clock_skew(&self) -> Option<chrono::Duration> {
+        match self.clock_skew.lock() {
+            Ok(skew) => *skew,
+            Err(_) => None,
+        }
+    }
+
+    /// Keep up to `max_idle` connections open between requests, for both the whole pool and the API host.
+    /// The default, `DEFAULT_POOL_SIZE`, matches `BATCH_CONCURRENCY` so every worker in
+    /// `get_equities` can reuse its own connection. A smaller pool makes the extra workers
+    /// reconnect on every request, a larger one only helps when requests are made from more
+    /// threads than that. The client doesn't rate limit requests, so the pool size doesn't cap
+    /// concurrency either. Call this before `with_keep_alive`, which warms the pool it's given.
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::client::Client;
+    ///
+    /// let client = Client::new(None).with_pool_size(32);
+    /// ```
+    pub fn with_pool_size(mut self, max_idle: usize) -> Self {
+        self.agent_config.max_idle = max_idle;
+        self.agent = self.agent_config.build();
+        self
+    }
+
+    /// Accept any TLS certificate the API presents, including self-signed and expired ones and
+    /// ones for another host. This is for testing against a local or staging server only: it lets
+    /// anyone on the network read and change every request, credentials included.
+    /// It only exists with the `dangerous-testing` feature, so a build without it that calls this
+    /// fails to compile rather than quietly connecting insecurely.
+    /// Call this before `with_keep_alive`.
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::client::Client;
+    ///
+    /// let client = Client::new(None).danger_accept_invalid_certs(true);
+    /// ```
+    #[cfg(feature = "dangerous-testing")]
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.agent_config.accept_invalid_certs = accept;
+        self.agent = self.agent_config.build();
+        self
+    }
+
+    /// Fail a request with `MoabError::ServerTimeoutError(TimeoutPhase::Connect)` if connecting
+    /// to the API takes longer than `timeout`. Connections don't time out by default.
+    /// Call this before `with_keep_alive`, which keeps the connections it's given warm.
+    /// # Examples
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use moabdb::client::Client;
+    ///
+    /// let client = Client::new(None)
+    ///     .with_connect_timeout(Duration::from_secs(2))
+    ///     .with_overall_timeout(Duration::from_secs(300));
+    /// ```
+    pub fn with_connect_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.agent_config.connect_timeout = Some(timeout);
+        self.agent = self.agent_config.build();
+        self
+    }
+
+    /// Fail a request with `MoabError::ServerTimeoutError(TimeoutPhase::FirstByte)` if the server
+    /// sends nothing for longer than `timeout`, either before the response starts or while it's
+    /// being read, so a stalled server is caught without limiting how long a large download takes.
+    /// There's no timeout by default. Call this before `with_keep_alive`.
+    pub fn with_first_byte_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.agent_config.first_byte_timeout = Some(timeout);
+        self.agent = self.agent_config.build();
+        self
+    }
+
+    /// Fail a request with `MoabError::ServerTimeoutError(TimeoutPhase::Overall)` if the whole
+    /// request, from connecting to reading the last byte of the response, takes longer than `timeout`.
+    /// This includes time spent waiting on `with_bandwidth_limit`. There's no timeout by default.
+    pub fn with_overall_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.overall_timeout = Some(timeout);
+        self
+    }
+
+    /// Read responses no faster than `bytes_per_second`, so large backfills don't saturate a
+    /// shared or metered connection. The limit is shared by every request the client makes,
+    /// including those from several threads, and allows bursts of up to a second's worth of bytes.
+    /// It caps the bytes read after the response headers, which are the bulk of a response.
+    /// There's no limit by default.
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::client::Client;
+    ///
+    /// // 2 MB/s
+    /// let client = Client::new(None).with_bandwidth_limit(2_000_000);
+    /// ```
+    pub fn with_bandwidth_limit(mut self, bytes_per_second: u64) -> Self {
+        self.throttle = Some(Throttle::new(bytes_per_second));
+        self
+    }
+
+    /// Stop making requests after `threshold` consecutive transport errors or 5xx responses,
+    /// failing every request immediately with `MoabError::CircuitOpen` instead. After `cooldown`
+    /// one request is let through to probe the API, closing the circuit if it succeeds and
+    /// restarting the cooldown if it fails. There's no circuit breaker by default.
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::client::Client;
+    ///
+    /// let client = Client::new(None).with_circuit_breaker(5, std::time::Duration::from_secs(30));
+    /// ```
+    pub fn with_circuit_breaker(mut self, threshold: u32, cooldown: std::time::Duration) -> Self {
+        self.circuit = Some(CircuitBreaker::new(threshold, cooldown));
+        self
+    }
+
+    /// Whether the circuit breaker is open, meaning the API appears to be down.
+    /// Always false without `with_circuit_breaker`.
+    pub fn circuit_open(&self) -> bool {
+        match &self.circuit {
+            Some(circuit) => circuit.is_open(),
+            None => false,
+        }
+    }
+
+    /// Keep the pooled connection warm by sending a lightweight `HEAD` request to the API
+    /// every `interval` from a background thread, so the first request after an idle
+    /// period doesn't pay for a new TLS handshake. The thread stops when the client is dropped.
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::client::Client;
+    ///
+    /// let client = Client::new(None).with_keep_alive(std::time::Duration::from_secs(30));
+    /// ```
+    #[cfg(feature = "keep-alive")]
+    pub fn with_keep_alive(mut self, interval: std::time::Duration) -> Self {
+        use std::sync::mpsc::{self, RecvTimeoutError};
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let agent = self.agent.clone();
+        std::thread::spawn(move || loop {
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {
+                    let _ = agent.head(API_URL).call();
+                }
+                _ => return,
+            }
+        });
+        self.keep_alive = Some(stop);
+        self
+    }
+
+    /// Get the equity data for a given ticker, see `moabdb::get_equity`
+    pub fn get_equity(
+        &self,
+        ticker: impl AsRef<str>,
+        window: Window,
+        intraday: bool,
+    ) -> Result<DataFrame, MoabError> {
+        let ticker = ticker.as_ref();
+        if !intraday {
+            return self.fetch_equity(ticker, window, false);
+        }
+        let df = self.fetch_equity(ticker, self.limit_intraday(window)?, true);
+        if !self.intraday_fallback {
+            return df;
+        }
+        match df {
+            Err(MoabError::NotFound) => (),
+            Ok(df) if df.height() == 0 => (),
+            df => return df,
+        }
+        self.fetch(ticker, window, equity_datatype(false), true)
+    }
+
+    /// Get the equity data for a given ticker as another user, using `credentials` for just this
+    /// request without changing the client's credentials. None makes the request unauthenticated.
+    /// The request is always sent on its own rather than shared with identical requests in flight,
+    /// and neither the credentials provider nor any fallback is used if it fails.
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::{client::Client, credentials::Credentials, window::WindowBuilder, window::WindowLength};
+    ///
+    /// let client = Client::new(None);
+    /// let admin = Credentials::new("admin", "admin-token");
+    /// let window = WindowBuilder::new()
+    ///     .length(WindowLength::Months(3))
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let df = client.get_equity_as("AAPL", window, false, Some(admin)).unwrap();
+    /// ```
+    pub fn get_equity_as(
+        &self,
+        ticker: impl AsRef<str>,
+        window: Window,
+        intraday: bool,
+        credentials: Option<Credentials>,
+    ) -> Result<DataFrame, MoabError> {
+        let window = if intraday {
+            self.limit_intraday(window)?
+        } else {
+            window
+        };
+        let datatype = equity_datatype(intraday);
+        self.attempt(ticker.as_ref(), window, datatype, &credentials, false)
+    }
+
+    /// Fetch equity data, retrying short frames of recent data as set by `with_short_frame_retries`
+    fn fetch_equity(
+        &self,
+        ticker: &str,
+        window: Window,
+        intraday: bool,
+    ) -> Result<DataFrame, MoabError> {
+        let datatype = equity_datatype(intraday);
+        let mut df = self.fetch(ticker, window, datatype, false);
+        let Some((retries, delay)) = self.short_frame_retries else {
+            return df;
+        };
+        let Some(minimum) = self.short_frame_minimum(window, intraday) else {
+            return df;
+        };
+        for _ in 0..retries {
+            match &df {
+                Ok(frame) if (frame.height() as f64) < minimum => (),
+                _ => break,
+            }
+            std::thread::sleep(delay);
+            df = self.fetch(ticker, window, datatype, false);
+        }
+        df
+    }
+
+    /// The fewest rows a frame for `window` can hold before `with_short_frame_retries` retries it,
+    /// or None if it isn't retried at all
+    fn short_frame_minimum(&self, window: Window, intraday: bool) -> Option<f64> {
+        let now = chrono::Utc::now().naive_utc();
+        // Sessions only filter intraday bars, and the regular session is what's estimated
+        let filtered = intraday
+            && !matches!(
+                self.session,
+                None | Some((Session::All | Session::Regular, _))
+            );
+        if self.row_limit.is_some()
+            || filtered
+            || window.end < now - chrono::Duration::hours(RECENT_DATA_HOURS)
+        {
+            return None;
+        }
+        let expected = Window {
+            end: window.end.min(now),
+            ..window
+        }
+        .estimate_rows(intraday, Market::Nyse);
+        Some(expected as f64 * self.short_frame_threshold)
+    }
+
+    /// Apply `max_intraday_lookback` to an intraday window
+    fn limit_intraday(&self, mut window: Window) -> Result<Window, MoabError> {
+        let Some(lookback) = self.max_intraday_lookback else {
+            return Ok(window);
+        };
+        let earliest = match chrono::Utc::now().naive_utc().checked_sub_signed(lookback) {
+            Some(earliest) => earliest,
+            None => return Ok(window),
+        };
+        if window.start >= earliest {
+            return Ok(window);
+        }
+        if !self.clamp_intraday || window.end < earliest {
+            return Err(MoabError::RequestError);
+        }
+        window.start = earliest;
+        Ok(window)
+    }
+
+    /// Get the data for a given symbol and raw datatype, see `moabdb::get_data`
+    pub fn get_data(
+        &self,
+        symbol: impl AsRef<str>,
+        window: Window,
+        datatype: impl AsRef<str>,
+    ) -> Result<DataFrame, MoabError> {
+        self.fetch(symbol.as_ref(), window, datatype.as_ref(), false)
+    }
+
+    /// Get the most recent `n` bars for a given ticker, see `moabdb::get_equity_last_n`
+    pub fn get_equity_last_n(
+        &self,
+        ticker: impl AsRef<str>,
+        n: usize,
+        intraday: bool,
+    ) -> Result<DataFrame, MoabError> {
+        // Generous enough to cover weekends and holidays
+        let days = if intraday {
+            (n / INTRADAY_BARS_PER_DAY + 1)
+                .saturating_mul(2)
+                .saturating_add(7)
+        } else {
+            n.saturating_mul(2).saturating_add(14)
+        };
+        let days = i64::try_from(days).unwrap_or(i64::MAX);
+        let window = match WindowBuilder::new()
+            .length(WindowLength::Days(days))
+            .build()
+        {
+            Ok(window) => window,
+            Err(_) => return Err(MoabError::RequestError),
+        };
+        let df = self.get_equity(ticker, window, intraday)?;
+        let df = frame::sort_and_dedup(&df, self.time_col())?;
+        Ok(df.tail(Some(n)))
+    }
+
+    /// Get the close of the most recent daily bar, see `moabdb::get_last_close`
+    pub fn get_last_close(&self, ticker: impl AsRef<str>) -> Result<f64, MoabError> {
+        last_close(&self.get_equity_last_n(ticker, 1, false)?)
+    }
+
+    /// Get equity data as a lazy scan of the received parquet, see `moabdb::get_equity_scan`
+    #[cfg(feature = "lazy")]
+    pub fn get_equity_scan(
+        &self,
+        ticker: impl AsRef<str>,
+        window: Window,
+        intraday: bool,
+    ) -> Result<polars::prelude::LazyFrame, MoabError> {
+        let datatype = equity_datatype(intraday);
+        self.fetch_with(
+            ticker.as_ref(),
+            window,
+            datatype,
+            false,
+            &|body| match parse_body(body) {
+                Ok(resp) => {
+                    let code = Some(resp.code);
+                    let lf = check_status(&resp).and_then(|()| {
+                        match crate::scan::scan_parquet(resp.data) {
+                            Ok(lf) => Ok(lf),
+                            Err(_) => Err(MoabError::DecodeError),
+                        }
+                    });
+                    (code, lf)
+                }
+                Err(e) => (None, Err(e)),
+            },
+        )
+    }
+
+    /// Get the undecoded response to an equity request, see `moabdb::get_equity_raw_response`
+    pub fn get_equity_raw_response(
+        &self,
+        ticker: impl AsRef<str>,
+        window: Window,
+        intraday: bool,
+    ) -> Result<Vec<u8>, MoabError> {
+        let datatype = equity_datatype(intraday);
+        self.fetch_with(ticker.as_ref(), window, datatype, false, &|body| {
+            let bytes = raw_body(body);
+            let code = match &bytes {
+                Ok(bytes) if is_parquet(bytes) => Some(200),
+                Ok(bytes) => protocol::Response::deserialize(bytes)
+                    .ok()
+                    .map(|resp| resp.code),
+                Err(_) => None,
+            };
+            (code, bytes)
+        })
+    }
+
+    /// Get the equity data for a given ticker from `last` until now, see `moabdb::get_equity_since`
+    pub fn get_equity_since(
+        &self,
+        ticker: impl AsRef<str>,
+        last: NaiveDateTime,
+        intraday: bool,
+        drop_boundary: bool,
+    ) -> Result<DataFrame, MoabError> {
+        // Timestamps have a resolution of one second, so this skips exactly the bar at `last`
+        let start = if drop_boundary {
+            last.checked_add_signed(chrono::Duration::seconds(1))
+        } else {
+            Some(last)
+        };
+        let window = match start {
+            Some(start) => WindowBuilder::new()
+                .start(start)
+                .end(chrono::Utc::now().naive_utc())
+                .build(),
+            None => Err("Window is out of range".to_string()),
+        };
+        match window {
+            Ok(window) => self.get_equity(ticker, window, intraday),
+            Err(_) => Err(MoabError::RequestError),
+        }
+    }
+
+    /// Poll for new intraday bars every `interval`, calling `callback` with the bars that arrived
+    /// since the last call, until it returns `ControlFlow::Break`.
+    /// The first call gets the most recent bar. After that the callback is only called when new bars
+    /// arrive, or with the error when a request fails. Ticks are scheduled from when polling
+    /// started so slow requests don't make them drift, ticks missed by a request that took longer
+    /// than `interval` are skipped, and each tick is delayed by a random jitter of up to a tenth
+    /// of the interval. This blocks the calling thread until the callback stops it.
+    /// # Examples
+    /// ```no_run
+    /// use std::{ops::ControlFlow, time::Duration};
+    /// use moabdb::client::Client;
+    ///
+    /// let client = Client::new(None);
+    /// let mut updates = 0;
+    /// client.poll_latest("AAPL", Duration::from_secs(60), |bars| {
+    ///     println!("{:?}", bars);
+    ///     updates += 1;
+    ///     if updates < 10 {
+    ///         ControlFlow::Continue(())
+    ///     } else {
+    ///         ControlFlow::Break(())
+    ///     }
+    /// });
+    /// ```
+    pub fn poll_latest(
+        &self,
+        ticker: impl AsRef<str>,
+        interval: std::time::Duration,
+        mut callback: impl FnMut(Result<DataFrame, MoabError>) -> ControlFlow<()>,
+    ) {
+        let ticker = ticker.as_ref();
+        let time_col = self.time_col();
+        let started = Instant::now();
+        let mut last: Option<NaiveDateTime> = None;
+        for tick in 0u32.. {
+            let bars = match last {
+                Some(last) => self.get_equity_since(ticker, last, true, true),
+                None => self.get_equity_last_n(ticker, 1, true),
+            };
+            let bars = match bars {
+                // The server has nothing newer than the last bar
+                Err(MoabError::NotFound) => None,
+                Ok(bars) if bars.height() == 0 => None,
+                Ok(bars) => match frame::time_range(&bars, time_col) {
+                    Ok(range) => {
+                        last = range.map(|range| range.end).or(last);
+                        Some(Ok(bars))
+                    }
+                    Err(e) => Some(Err(e)),
+                },
+                Err(e) => Some(Err(e)),
+            };
+            if let Some(bars) = bars {
+                if callback(bars).is_break() {
+                    return;
+                }
+            }
+
+            let elapsed = started.elapsed();
+            let Some(next) = (tick + 1..)
+                .map_while(|tick| interval.checked_mul(tick))
+                .find(|next| *next > elapsed)
+            else {
+                return;
+            };
+            std::thread::sleep(next - elapsed + jitter(interval.mul_f64(POLL_JITTER)));
+        }
+    }
+
+    /// Get all the equity data the server has for a given ticker, see `moabdb::get_equity_full_history`
+    pub fn get_equity_full_history(
+        &self,
+        ticker: impl AsRef<str>,
+        intraday: bool,
+    ) -> Result<(DataFrame, Option<Window>), MoabError> {
+        let df = self.get_equity(ticker, full_history()?, intraday)?;
+        let range = frame::time_range(&df, self.time_col())?;
+        Ok((df, range))
+    }
+
+    /// Get the range of time the server has data for, see `moabdb::get_available_range`
+    pub fn get_available_range(
+        &self,
+        symbol: impl AsRef<str>,
+        datatype: impl AsRef<str>,
+    ) -> Result<Window, MoabError> {
+        let df = self.get_data(symbol, full_history()?, datatype)?;
+        match frame::time_range(&df, self.time_col())? {
+            Some(range) => Ok(range),
+            None => Err(MoabError::NotFound),
+        }
+    }
+
+    /// Write the equity data for a given ticker to `writer` as CSV, see `moabdb::write_equity_csv`
+    pub fn write_equity_csv(
+        &self,
+        ticker: impl AsRef<str>,
+        window: Window,
+        intraday: bool,
+        writer: &mut impl Write,
+    ) -> Result<usize, MoabError> {
+        let df = self.get_equity(ticker, window, intraday)?;
+        frame::write_csv(&df, writer)
+    }
+
+    /// Write the equity data for a given ticker as Arrow IPC, see `moabdb::write_equity_ipc`
+    #[cfg(feature = "ipc")]
+    pub fn write_equity_ipc(
+        &self,
+        ticker: impl AsRef<str>,
+        window: Window,
+        intraday: bool,
+        writer: &mut impl Write,
+    ) -> Result<usize, MoabError> {
+        let df = self.get_equity(ticker, window, intraday)?;
+        frame::write_ipc(&df, writer)
+    }
+
+    /// Get the equity data for a given ticker as JSON rows, see `moabdb::get_equity_json_rows`
+    #[cfg(feature = "json")]
+    pub fn get_equity_json_rows(
+        &self,
+        ticker: impl AsRef<str>,
+        window: Window,
+        intraday: bool,
+    ) -> Result<Vec<serde_json::Value>, MoabError> {
+        frame::to_json_rows(&self.get_equity(ticker, window, intraday)?)
+    }
+
+    /// Get the equity data for several tickers over the same window, see `moabdb::get_equities`.
+    /// Up to `BATCH_CONCURRENCY` requests are in flight at once.
+    pub fn get_equities(
+        &self,
+        tickers: &[impl AsRef<str>],
+        window: Window,
+        intraday: bool,
+    ) -> HashMap<String, Result<DataFrame, MoabError>> {
+        self.get_equities_ordered(tickers, window, intraday)
+            .into_iter()
+            .collect()
+    }
+
+    /// Get the equity data for several tickers over the same window, in the order of `tickers`,
+    /// see `moabdb::get_equities_ordered`. Up to `BATCH_CONCURRENCY` requests are in flight at once.
+    pub fn get_equities_ordered(
+        &self,
+        tickers: &[impl AsRef<str>],
+        window: Window,
+        intraday: bool,
+    ) -> Vec<(String, Result<DataFrame, MoabError>)> {
+        let tickers: Vec<String> = tickers.iter().map(|t| t.as_ref().to_string()).collect();
+        let results = batch(&tickers, |ticker| self.get_equity(ticker, window, intraday));
+        tickers.into_iter().zip(results).collect()
+    }
+
+    /// Send the equity data for several tickers over the same window into channels as each
+    /// request completes, see `moabdb::get_equities_to_channel`.
+    /// Up to `BATCH_CONCURRENCY` requests are in flight at once.
+    pub fn get_equities_to_channel(
+        &self,
+        tickers: &[impl AsRef<str>],
+        window: Window,
+        intraday: bool,
+        frames: Sender<(String, DataFrame)>,
+        errors: Sender<(String, MoabError)>,
+    ) {
+        let tickers: Vec<String> = tickers.iter().map(|t| t.as_ref().to_string()).collect();
+        // A receiver that hung up simply misses the results sent after it did
+        let results = batch(&tickers, |ticker| {
+            match self.get_equity(ticker, window, intraday) {
+                Ok(df) => {
+                    let _ = frames.send((ticker.clone(), df));
+                }
+                Err(e) => {
+                    let _ = errors.send((ticker.clone(), e));
+                }
+            }
+            Ok(())
+        });
+        // Only a request that panicked has no result sent yet
+        for (ticker, result) in tickers.into_iter().zip(results) {
+            if let Err(e) = result {
+                let _ = errors.send((ticker, e));
+            }
+        }
+    }
+
+    /// Get the equity data for a ticker over several windows, in the order of `windows`,
+    /// see `moabdb::get_equity_windows`. Up to `BATCH_CONCURRENCY` requests are in flight at once.
+    pub fn get_equity_windows(
+        &self,
+        ticker: impl AsRef<str>,
+        windows: &[Window],
+        intraday: bool,
+    ) -> Vec<Result<DataFrame, MoabError>> {
+        let ticker = ticker.as_ref();
+        batch(windows, |window| self.get_equity(ticker, *window, intraday))
+    }
+
+    /// Get the equity data for a given ticker along with how much of the window it covers,
+    /// see `moabdb::get_equity_with_coverage`
+    pub fn get_equity_with_coverage(
+        &self,
+        ticker: impl AsRef<str>,
+        window: Window,
+        intraday: bool,
+    ) -> Result<(DataFrame, Coverage), MoabError> {
+        let df = self.get_equity(ticker, window, intraday)?;
+        let time_col = self.time_col();
+        let coverage = frame::coverage(&df, time_col, window, intraday)?;
+        Ok((df, coverage))
+    }
+
+    /// Get a single wide frame of one column across several tickers, see `moabdb::get_panel`
+    pub fn get_panel(
+        &self,
+        tickers: &[impl AsRef<str>],
+        window: Window,
+        intraday: bool,
+        value_col: &str,
+    ) -> Result<(DataFrame, Vec<String>), MoabError> {
+        let mut frames = HashMap::new();
+        let mut missing = Vec::new();
+        for (ticker, df) in self.get_equities(tickers, window, intraday) {
+            match df {
+                Ok(df) if frame::resolve_column(&df, value_col).is_some() => {
+                    frames.insert(ticker, df);
+                }
+                _ => missing.push(ticker),
+            }
+        }
+        missing.sort();
+        let panel = frame::to_panel(&frames, self.time_col(), value_col)?;
+        Ok((panel, missing))
+    }
+
+    /// Get the equity data for several tickers registered as tables of a SQL context, see `moabdb::get_sql_context`
+    #[cfg(feature = "sql")]
+    pub fn get_sql_context(
+        &self,
+        tickers: &[impl AsRef<str>],
+        window: Window,
+        intraday: bool,
+    ) -> Result<(polars::sql::SQLContext, Vec<String>), MoabError> {
+        use polars::prelude::IntoLazy;
+
+        let mut ctx = match polars::sql::SQLContext::try_new() {
+            Ok(ctx) => ctx,
+            Err(_) => return Err(MoabError::RequestError),
+        };
+        let mut missing = Vec::new();
+        for (ticker, df) in self.get_equities(tickers, window, intraday) {
+            match df {
+                Ok(df) => ctx.register(&ticker, df.lazy()),
+                Err(_) => missing.push(ticker),
+            }
+        }
+        missing.sort();
+        Ok((ctx, missing))
+    }
+
+    /// Get the correlation matrix of daily returns across several tickers, see `moabdb::correlation_matrix`
+    pub fn correlation_matrix(
+        &self,
+        tickers: &[impl AsRef<str>],
+        window: Window,
+        value_col: &str,
+    ) -> Result<DataFrame, MoabError> {
+        let (panel, _) = self.get_panel(tickers, window, false, value_col)?;
+        let mut corr = frame::correlation(&panel, self.time_col())?;
+        if self.symbol_column != frame::SYMBOL_COLUMN
+            && corr
+                .rename(frame::SYMBOL_COLUMN, &self.symbol_column)
+                .is_err()
+        {
+            return Err(MoabError::DecodeError);
+        }
+        Ok(corr)
+    }
+
+    /// Fetch equity data and append it to `target`, see `moabdb::extend_frame`
+    pub fn extend_frame(
+        &self,
+        target: &mut DataFrame,
+        ticker: impl AsRef<str>,
+        window: Window,
+        intraday: bool,
+    ) -> Result<(), MoabError> {
+        let df = self.get_equity(ticker, window, intraday)?;
+        frame::extend(target, &df)
+    }
+
+    /// Compute a single value over a window of daily equity data, see `moabdb::get_aggregate`
+    pub fn get_aggregate(
+        &self,
+        ticker: impl AsRef<str>,
+        window: Window,
+        agg: &Aggregation,
+    ) -> Result<f64, MoabError> {
+        let df = self.get_equity(ticker, window, false)?;
+        frame::aggregate(&df, agg)
+    }
+
+    /// Fetch the data, sharing the result of an identical request if one is already in flight
+    /// from another thread instead of making it again.
+    /// `degraded` flags the request as a fallback in its metrics event.
+    fn fetch(
+        &self,
+        symbol: &str,
+        window: Window,
+        datatype: &str,
+        degraded: bool,
+    ) -> Result<DataFrame, MoabError> {
+        let (start, end) = window.request_timestamps();
+        let key = (symbol.to_string(), datatype.to_string(), start, end);
+        self.in_flight.run(key, || {
+            self.fetch_uncoalesced(symbol, window, datatype, degraded)
+        })
+    }
+
+    fn fetch_uncoalesced(
+        &self,
+        symbol: &str,
+        window: Window,
+        datatype: &str,
+        degraded: bool,
+    ) -> Result<DataFrame, MoabError> {
+        self.fetch_with(symbol, window, datatype, degraded, &|body| {
+            self.read_frame(body, symbol, datatype)
+        })
+    }
+
+    /// Send a request with the client's credentials, asking the provider for new ones if they're rejected
+    fn fetch_with<T>(
+        &self,
+        symbol: &str,
+        window: Window,
+        datatype: &str,
+        degraded: bool,
+        read: &BodyReader<T>,
+    ) -> Result<T, MoabError> {
+        let credentials = match self.credentials.read() {
+            Ok(credentials) => credentials.clone(),
+            Err(_) => None,
+        };
+        let out = self.attempt_with(symbol, window, datatype, &credentials, degraded, read);
+        let out = match (out, &self.credentials_provider) {
+            (Err(MoabError::Unauthorized | MoabError::AuthenticationRequired), Some(provider)) => {
+                let credentials = provider();
+                self.set_credentials(credentials.clone());
+                self.attempt_with(symbol, window, datatype, &credentials, degraded, read)
+            }
+            (out, _) => out,
+        };
+        match out {
+            Err(MoabError::Unauthorized)
+                if self.fallback_unauthenticated && !self.require_credentials =>
+            {
+                self.attempt_with(symbol, window, datatype, &None, true, read)
+            }
+            out => out,
+        }
+    }
+
+    fn attempt(
+        &self,
+        symbol: &str,
+        window: Window,
+        datatype: &str,
+        credentials: &Option<Credentials>,
+        degraded: bool,
+    ) -> Result<DataFrame, MoabError> {
+        self.attempt_with(symbol, window, datatype, credentials, degraded, &|body| {
+            self.read_frame(body, symbol, datatype)
+        })
+    }
+
+    /// Decode a response body into a frame with the client's output options applied
+    fn read_frame(
+        &self,
+        body: Vec<u8>,
+        symbol: &str,
+        datatype: &str,
+    ) -> (Option<u32>, Result<DataFrame, MoabError>) {
+        match parse_body(body) {
+            Ok(resp) => {
+                let code = Some(resp.code);
+                let df = decode(resp).and_then(|df| self.postprocess(df, symbol, datatype));
+                (code, df)
+            }
+            Err(e) => (None, Err(e)),
+        }
+    }
+
+    fn attempt_with<T>(
+        &self,
+        symbol: &str,
+        window: Window,
+        datatype: &str,
+        credentials: &Option<Credentials>,
+        degraded: bool,
+        read: &BodyReader<T>,
+    ) -> Result<T, MoabError> {
+        // Tell a missing login apart from rejected credentials
+        let unauthenticated = match credentials {
+            Some(creds) => creds.username.is_empty() && creds.token.is_empty(),
+            None => true,
+        };
+        if unauthenticated && self.require_credentials {
+            return Err(MoabError::AuthenticationRequired);
+        }
+        // Held until the result is recorded, so a panic in between releases a half open circuit
+        let permit = match self.circuit.as_ref().map(CircuitBreaker::allow) {
+            Some(None) => return Err(MoabError::CircuitOpen),
+            Some(permit) => permit,
+            None => None,
+        };
+        #[cfg(feature = "otel")]
+        let span = crate::otel::RequestSpan::start(symbol, datatype);
+        #[cfg(feature = "otel")]
+        let headers = span.headers();
+        #[cfg(not(feature = "otel"))]
+        let headers = HashMap::new();
+
+        let started = Instant::now();
+        let body = self.request_body(symbol, window, datatype, credentials, &headers);
+        let bytes = body.as_ref().map_or(0, |body| body.len());
+        let (code, out) = match body {
+            Ok(body) => read(body),
+            Err(e) => (None, Err(e)),
+        };
+        self.intercept_response(symbol, datatype, code, bytes, started);
+        if let Some(permit) = permit {
+            permit.record(trips_circuit(code, &out));
+        }
+        let out = match out {
+            Err(MoabError::Unauthorized) if unauthenticated => {
+                Err(MoabError::AuthenticationRequired)
+            }
+            out => out,
+        };
+        #[cfg(feature = "otel")]
+        span.end(code, out.is_ok());
+
+        if let Some(metrics) = &self.metrics {
+            metrics.record(RequestEvent {
+                symbol: symbol.to_string(),
+                datatype: datatype.to_string(),
+                duration: started.elapsed(),
+                code,
+                success: out.is_ok(),
+                degraded,
+                tag: tag::current(),
+            });
+        }
+        out
+    }
+
+    /// The time key of returned frames, see `Client::with_time_column`
+    fn time_col(&self) -> &str {
+        self.time_column.as_deref().unwrap_or(frame::TIME_COLUMN)
+    }
+
+    /// Apply the client's output options to a decoded frame
+    fn postprocess(
+        &self,
+        df: DataFrame,
+        symbol: &str,
+        datatype: &str,
+    ) -> Result<DataFrame, MoabError> {
+        let df = if self.canonical_columns {
+            frame::canonicalize_columns(df)?
+        } else {
+            df
+        };
+        match &self.expected_columns {
+            Some(expected) => frame::check_columns(&df, expected)?,
+            None if datatype == equity_datatype(false) || datatype == equity_datatype(true) => {
+                frame::check_columns(&df, frame::EQUITY_COLUMNS)?
+            }
+            None => (),
+        }
+        let df = match &self.time_column {
+            Some(time_col) => rename_time_column(df, time_col)?,
+            None => df,
+        };
+        let time_col = self.time_col();
+        let df = match self.session {
+            Some((session, market)) if datatype == equity_datatype(true) => {
+                frame::filter_session(df, time_col, session, market)?
+            }
+            _ => df,
+        };
+        let df = if self.sorted_output {
+            frame::sort_and_dedup(&df, time_col)?
+        } else {
+            df
+        };
+        let df = match &self.time_column {
+            Some(time_col) => frame::to_time_index(df, time_col)?,
+            None => df,
+        };
+        let df = if self.tag_symbol {
+            frame::tag_symbol(df, symbol, &self.symbol_column)?
+        } else {
+            df
+        };
+        let df = match self.price_scale {
+            Some(scale) => frame::scale_prices(df, scale)?,
+            None => df,
+        };
+        let df = match self.precision {
+            Some(precision) => frame::with_precision(df, precision)?,
+            None => df,
+        };
+        let df = match &self.output {
+            Some(spec) => frame::apply_output(df, spec, time_col)?,
+            None => df,
+        };
+        let df = match self.row_limit {
+            Some(limit) => df.head(Some(limit)),
+            None => df,
+        };
+        Ok(df)
+    }
+
+    fn intercept_response(
+        &self,
+        symbol: &str,
+        datatype: &str,
+        code: Option<u32>,
+        bytes: usize,
+        started: Instant,
+    ) {
+        if self.interceptors.is_empty() {
+            return;
+        }
+        let event = ResponseEvent {
+            symbol: symbol.to_string(),
+            datatype: datatype.to_string(),
+            code,
+            bytes,
+            duration: started.elapsed(),
+            tag: tag::current(),
+        };
+        for interceptor in &self.interceptors {
+            interceptor.after_response(&event);
+        }
+    }
+
+    /// Make the request, returning the response body as received
+    fn request_body(
+        &self,
+        symbol: &str,
+        window: Window,
+        datatype: &str,
+        credentials: &Option<Credentials>,
+        headers: &HashMap<String, String>,
+    ) -> Result<Vec<u8>, MoabError> {
+        let (start, end) = window.request_timestamps();
+        let req = protocol::Request {
+            symbol: symbol.to_string(),
+            start,
+            end,
+            datatype: datatype.to_string(),
+            username: match credentials {
+                Some(ref creds) => creds.username.to_string(),
+                None => "".to_string(),
+            },
+            token: match credentials {
+                Some(ref creds) => creds.token.to_string(),
+                None => "".to_string(),
+            },
+            version: protocol::PROTOCOL_VERSION,
+        };
+        let mut outgoing = OutgoingRequest {
+            symbol: req.symbol.clone(),
+            datatype: req.datatype.clone(),
+            start,
+            end,
+            username: req.username.clone(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            tag: tag::current(),
+        };
+        for interceptor in &self.interceptors {
+            interceptor.before_request(&mut outgoing);
+        }
+        let req = req.b64();
+
+        let started = Instant::now();
+        let resp = call_api(
+            &self.agent,
+            API_URL,
+            &req,
+            &outgoing.headers,
+            started,
+            self.overall_timeout,
+        )?;
+
+        if let Some(skew) = measure_skew(&resp) {
+            if let Ok(mut clock_skew) = self.clock_skew.lock() {
+                *clock_skew = Some(skew);
+            }
+            if let Some(max_skew) = self.max_clock_skew {
+                if skew.abs() > max_skew {
+                    return Err(MoabError::ClockSkew);
+                }
+            }
+        }
+        // Size the buffer up front when the length is known, but don't trust a huge header
+        let length = resp
+            .header("content-length")
+            .and_then(|length| length.trim().parse::<usize>().ok())
+            .map_or(0, |length| length.min(MAX_PREALLOCATED_BYTES));
+        let mut resp = resp.into_reader();
+
+        // Read the resp to end
+        let mut buffer = Vec::with_capacity(length);
+        let read = match &self.throttle {
+            Some(throttle) => throttle.read_to_end(&mut resp, &mut buffer),
+            None => resp.read_to_end(&mut buffer),
+        };
+        match read {
+            Ok(_) => Ok(buffer),
+            Err(e) if is_timeout(&e) => Err(MoabError::ServerTimeoutError(read_timeout_phase(
+                started.elapsed(),
+                self.overall_timeout,
+            ))),
+            Err(_) => Err(MoabError::TransportError),
+        }
+    }
+}
+
+/// Make a request for each item from up to `BATCH_CONCURRENCY` threads at once,
+/// returning the results in the order of `items`
+fn batch<T: Sync, R: Send>(
+    items: &[T],
+    request: impl Fn(&T) -> Result<R, MoabError> + Sync,
+) -> Vec<Result<R, MoabError>> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(HashMap::with_capacity(items.len()));
+    let tag = tag::current();
+    std::thread::scope(|scope| {
+        for _ in 0..BATCH_CONCURRENCY.min(items.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else {
+                    return;
+                };
+                let df = tag::scoped(tag.clone(), || request(item));
+                if let Ok(mut results) = results.lock() {
+                    results.insert(i, df);
+                }
+            });
+        }
+    });
+    let mut results = match results.into_inner() {
+        Ok(results) => results,
+        Err(e) => e.into_inner(),
+    };
+    // A result is only missing if the thread making the request panicked
+    (0..items.len())
+        .map(|i| {
+            results.remove(&i).unwrap_or_else(|| {
+                Err(MoabError::UnknownError(
+                    0,
+                    "The request panicked".to_string(),
+                ))
+            })
+        })
+        .collect()
+}
+
+/// Rename the server's time column to `name`, unless the frame already has a column called that
+fn rename_time_column(mut df: DataFrame, name: &str) -> Result<DataFrame, MoabError> {
+    if df.column(name).is_ok() {
+        return Ok(df);
+    }
+    let aliases = frame::CANONICAL_COLUMNS
+        .iter()
+        .filter(|(_, to)| *to == frame::TIME_COLUMN)
+        .map(|(from, _)| *from);
+    let server = std::iter::once(frame::TIME_COLUMN)
+        .chain(aliases)
+        .find(|col| df.column(col).is_ok());
+    match server {
+        Some(server) if df.rename(server, name).is_err() => Err(MoabError::DecodeError),
+        _ => Ok(df),
+    }
+}
+
+/// The close of the last bar of a frame
+fn last_close(df: &DataFrame) -> Result<f64, MoabError> {
+    let df = frame::canonicalize_columns(df.clone())?;
+    match frame::f64_values(&df, "close")?.last() {
+        Some(Some(close)) if !close.is_nan() => Ok(*close),
+        _ => Err(MoabError::NotFound),
+    }
+}
+
+/// Whether a request's result counts as a failure for the circuit breaker
+fn trips_circuit<T>(code: Option<u32>, out: &Result<T, MoabError>) -> bool {
+    match (code, out) {
+        // Maintenance says when to come back, which an open circuit would hide
+        (Some(MAINTENANCE_CODE), _) => false,
+        (Some(code), _) => (500..600).contains(&code),
+        (None, Err(MoabError::TransportError | MoabError::ServerTimeoutError(_))) => true,
+        _ => false,
+    }
+}
+
+/// A random duration up to `max`
+fn jitter(max: std::time::Duration) -> std::time::Duration {
+    use std::hash::{BuildHasher, Hasher};
+
+    // Every RandomState is seeded differently, which is random enough to spread out requests
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    max.mul_f64(random as f64 / u64::MAX as f64)
+}
+
+/// Send a request to `url`, following up to `MAX_REDIRECTS` redirects by hand.
+/// ureq only strips `Authorization` when it follows a redirect, but the credentials travel in
+/// `x-req`, so it and the other headers are only sent to the origin the request started at.
+fn call_api(
+    agent: &ureq::Agent,
+    url: &str,
+    req: &str,
+    headers: &[(String, String)],
+    started: Instant,
+    overall: Option<std::time::Duration>,
+) -> Result<ureq::Response, MoabError> {
+    let origin = match url::Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => return Err(MoabError::RequestError),
+    };
+    let mut target = origin.clone();
+    for _ in 0..=MAX_REDIRECTS {
+        let mut call = agent.get(target.as_str());
+        if target.origin() == origin.origin() {
+            call = call.set("x-req", req);
+            for (name, value) in headers {
+                call = call.set(name, value);
+            }
+        }
+        if let Some(timeout) = overall {
+            call = call.timeout(timeout.saturating_sub(started.elapsed()));
+        }
+        let resp = match call.call() {
+            Ok(resp) => resp,
+            Err(ureq::Error::Status(code, resp)) if u32::from(code) == MAINTENANCE_CODE => {
+                return Err(MoabError::Maintenance {
+                    retry_after: resp.header("retry-after").and_then(parse_retry_after),
+                })
+            }
+            Err(e) => {
+                return Err(match timeout_phase(&e, started.elapsed(), overall) {
+                    Some(phase) => MoabError::ServerTimeoutError(phase),
+                    None => MoabError::TransportError,
+                })
+            }
+        };
+        if !matches!(resp.status(), 301 | 302 | 303 | 307 | 308) {
+            return Ok(resp);
+        }
+        target = match resp
+            .header("location")
+            .and_then(|location| target.join(location).ok())
+        {
+            Some(next) => next,
+            None => return Err(MoabError::TransportError),
+        };
+    }
+    Err(MoabError::TooManyRedirects)
+}
+
+/// The datatype of daily or intraday equity data
+fn equity_datatype(intraday: bool) -> &'static str {
+    if intraday {
+        "intraday_stocks"
+    } else {
+        "daily_stocks"
+    }
+}
+
+/// The window from `FULL_HISTORY_START` until now
+fn full_history() -> Result<Window, MoabError> {
+    let now = chrono::Utc::now().timestamp();
+    match Window::try_from((FULL_HISTORY_START, now)) {
+        Ok(window) => Ok(window),
+        Err(_) => Err(MoabError::RequestError),
+    }
+}
+
+/// The magic bytes a parquet file starts with
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+
+/// Parse a response body, which is normally a base64 protobuf `Response`.
+/// A body that is plain parquet is taken as a successful response with that data.
+fn parse_body(body: Vec<u8>) -> Result<protocol::Response, MoabError> {
+    if is_parquet(&body) {
+        return Ok(wrap_parquet(body));
+    }
+    // Anything else that isn't base64 is usually an error page from a proxy or load balancer
+    if looks_like_error_page(&body) {
+        return Err(MoabError::UnexpectedBody(body_snippet(&body)));
+    }
+    let body = match String::from_utf8(body) {
+        Ok(body) => body,
+        Err(e) => return Err(MoabError::UnexpectedBody(body_snippet(e.as_bytes()))),
+    };
+    match body.try_into() {
+        Ok(resp) => Ok(resp),
+        Err(_) => Err(MoabError::DecodeError),
+    }
+}
+
+/// A plain parquet body as the successful response it stands for
+pub(crate) fn wrap_parquet(data: Vec<u8>) -> protocol::Response {
+    protocol::Response {
+        code: 200,
+        message: String::new(),
+        data,
+        version: 0,
+    }
+}
+
+/// Whether `bytes` are a plain parquet file rather than a protobuf `Response`
+pub(crate) fn is_parquet(bytes: &[u8]) -> bool {
+    bytes.starts_with(PARQUET_MAGIC)
+}
+
+/// A response body with only its base64 removed, so the protobuf is exactly as the server
+/// encoded it even if it can't be decoded. A plain parquet body is returned as it is.
+fn raw_body(body: Vec<u8>) -> Result<Vec<u8>, MoabError> {
+    if is_parquet(&body) {
+        return Ok(body);
+    }
+    if looks_like_error_page(&body) {
+        return Err(MoabError::UnexpectedBody(body_snippet(&body)));
+    }
+    match base64::decode(&body) {
+        Ok(bytes) => Ok(bytes),
+        Err(_) => Err(MoabError::DecodeError),
+    }
+}
+
+/// Whether a response body is an HTML or JSON page rather than base64
+fn looks_like_error_page(body: &[u8]) -> bool {
+    matches!(
+        body.iter().find(|b| !b.is_ascii_whitespace()),
+        Some(b'<' | b'{' | b'[')
+    )
+}
+
+/// The start of a response body as text, for diagnosing a response that isn't base64
+fn body_snippet(body: &[u8]) -> String {
+    let end = body.len().min(BODY_SNIPPET_BYTES);
+    String::from_utf8_lossy(&body[..end]).into_owned()
+}
+
+/// The options the agent every request is made with is built from
+struct AgentConfig {
+    /// The most idle connections kept open
+    max_idle: usize,
+    connect_timeout: Option<std::time::Duration>,
+    first_byte_timeout: Option<std::time::Duration>,
+    #[cfg(feature = "dangerous-testing")]
+    accept_invalid_certs: bool,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            max_idle: DEFAULT_POOL_SIZE,
+            connect_timeout: None,
+            first_byte_timeout: None,
+            #[cfg(feature = "dangerous-testing")]
+            accept_invalid_certs: false,
+        }
+    }
+}
+
+impl AgentConfig {
+    fn build(&self) -> ureq::Agent {
+        // Redirects are followed by `call_api`, which keeps the credentials on the API's origin
+        let mut agent = ureq::AgentBuilder::new()
+            .redirects(0)
+            .max_idle_connections(self.max_idle)
+            .max_idle_connections_per_host(self.max_idle);
+        if let Some(timeout) = self.connect_timeout {
+            agent = agent.timeout_connect(timeout);
+        }
+        if let Some(timeout) = self.first_byte_timeout {
+            agent = agent.timeout_read(timeout);
+        }
+        #[cfg(feature = "dangerous-testing")]
+        if self.accept_invalid_certs {
+            // Building the config only fails if ring can't do TLS at all,
+            // in which case certificates stay checked and the connection fails as usual
+            if let Some(config) = crate::danger::accept_invalid_certs() {
+                agent = agent.tls_config(config);
+            }
+        }
+        agent.build()
+    }
+}
+
+/// Whether an IO error is a socket timeout
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+    )
+}
+
+/// The phase a request that failed before its response could be read timed out in, if it timed out
+fn timeout_phase(
+    e: &ureq::Error,
+    elapsed: std::time::Duration,
+    overall: Option<std::time::Duration>,
+) -> Option<TimeoutPhase> {
+    let mut source = std::error::Error::source(e);
+    let timed_out = loop {
+        match source {
+            Some(inner) => match inner.downcast_ref::<std::io::Error>() {
+                Some(io) => break is_timeout(io),
+                None => source = inner.source(),
+            },
+            None => break false,
+        }
+    };
+    if !timed_out {
+        return None;
+    }
+    match read_timeout_phase(elapsed, overall) {
+        TimeoutPhase::FirstByte if e.kind() == ureq::ErrorKind::ConnectionFailed => {
+            Some(TimeoutPhase::Connect)
+        }
+        phase => Some(phase),
+    }
+}
+
+/// The phase a request that timed out while reading timed out in
+fn read_timeout_phase(
+    elapsed: std::time::Duration,
+    overall: Option<std::time::Duration>,
+) -> TimeoutPhase {
+    match overall {
+        Some(overall) if elapsed >= overall => TimeoutPhase::Overall,
+        _ => TimeoutPhase::FirstByte,
+    }
+}
+
+/// The error a response's version or status code maps to, if it isn't a successful response
+fn check_status(resp: &protocol::Response) -> Result<(), MoabError> {
+    if !resp.version_supported() {
+        return Err(MoabError::UnsupportedProtocol(resp.version));
+    }
+    match resp.code {
+        200 => Ok(()),
+        400 => Err(MoabError::RequestError),
+        401 => Err(MoabError::Unauthorized),
+        404 => Err(MoabError::NotFound),
+        500 => Err(MoabError::ServerInternalError),
+        MAINTENANCE_CODE => Err(MoabError::Maintenance { retry_after: None }),
+        code => Err(MoabError::UnknownError(code, resp.message.clone())),
+    }
+}
+
+/// The wait a `Retry-After` header asks for, given as either seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<std::time::Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(std::time::Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    // A date that has already passed means it's fine to retry now
+    Some(wait.to_std().unwrap_or_default())
+}
+
+/// The server's clock minus the local clock, from the response's `Date` header
+fn measure_skew(resp: &ureq::Response) -> Option<chrono::Duration> {
+    let date = resp.header("date")?;
+    let server = chrono::DateTime::parse_from_rfc2822(date).ok()?;
+    Some(server.with_timezone(&chrono::Utc) - chrono::Utc::now())
+}
+
+pub(crate) fn decode(resp: protocol::Response) -> Result<DataFrame, MoabError> {
+    check_status(&resp)?;
+    // The parquet reader can panic on malformed data, which shouldn't take down the caller
+    let df =
+        std::panic::catch_unwind(|| ParquetReader::new(std::io::Cursor::new(resp.data)).finish());
+    let df = match df {
+        Ok(Ok(df)) => df,
+        Ok(Err(_)) | Err(_) => return Err(MoabError::DecodeError),
+    };
+
+    Ok(df)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn days_ago(start: i64, end: i64) -> Window {
+        let now = chrono::Utc::now().naive_utc();
+        Window {
+            start: now - chrono::Duration::days(start),
+            end: now - chrono::Duration::days(end),
+            bound: crate::window::Bound::Inclusive,
+        }
+    }
+
+    #[test]
+    fn intraday_lookback_rejects_or_clamps() {
+        let lookback = chrono::Duration::days(30);
+        let rejecting = Client::new(None).with_max_intraday_lookback(lookback, false);
+        assert!(rejecting.limit_intraday(days_ago(10, 0)).is_ok());
+        assert!(rejecting.limit_intraday(days_ago(40, 0)).is_err());
+
+        let clamping = Client::new(None).with_max_intraday_lookback(lookback, true);
+        let window = clamping.limit_intraday(days_ago(40, 0)).unwrap();
+        assert!(window.end - window.start <= lookback);
+        assert!(clamping.limit_intraday(days_ago(50, 40)).is_err());
+    }
+
+    #[test]
+    fn required_credentials_stop_unauthenticated_requests() {
+        let client = Client::new(None).require_credentials(true);
+        let window = days_ago(5, 0);
+        assert!(matches!(
+            client.get_equity("AAPL", window, false),
+            Err(MoabError::AuthenticationRequired)
+        ));
+        let empty = Some(Credentials::new("", ""));
+        assert!(matches!(
+            client.get_equity_as("AAPL", window, false, empty),
+            Err(MoabError::AuthenticationRequired)
+        ));
+        assert!(matches!(
+            client.get_equity_raw_response("AAPL", window, false),
+            Err(MoabError::AuthenticationRequired)
+        ));
+        #[cfg(feature = "lazy")]
+        assert!(matches!(
+            client.get_equity_scan("AAPL", window, false),
+            Err(MoabError::AuthenticationRequired)
+        ));
+    }
+
+    #[test]
+    fn time_helpers_key_on_the_configured_column() {
+        use polars::prelude::NamedFrom;
+
+        assert_eq!(Client::new(None).time_col(), frame::TIME_COLUMN);
+        let client = Client::new(None).with_time_column("ts");
+        let df = polars::df!("ts" => &[120i64, 0, 60], "close" => &[3.0, 1.0, 2.0]).unwrap();
+        let range = frame::time_range(&df, client.time_col()).unwrap().unwrap();
+        assert_eq!(<(i64, i64)>::from(range), (0, 120));
+        let frames = HashMap::from([("AAPL".to_string(), df)]);
+        let panel = frame::to_panel(&frames, client.time_col(), "close").unwrap();
+        assert!(panel.column("ts").is_ok());
+    }
+
+    #[test]
+    fn time_column_renames_the_server_column() {
+        use polars::prelude::{DataType, NamedFrom};
+
+        let df = polars::df!(
+            "timestamp" => &[60i64, 0],
+            "open" => &[1.0, 2.0],
+            "high" => &[1.0, 2.0],
+            "low" => &[1.0, 2.0],
+            "close" => &[1.0, 2.0],
+            "volume" => &[10i64, 20],
+        )
+        .unwrap();
+        let client = Client::new(None).with_time_column("bar_time");
+        let df = client
+            .postprocess(df, "AAPL", equity_datatype(false))
+            .unwrap();
+        assert!(df.column(frame::TIME_COLUMN).is_err());
+        let time = df.column("bar_time").unwrap();
+        assert!(matches!(time.dtype(), DataType::Datetime(_, _)));
+        let range = frame::time_range(&df, client.time_col()).unwrap().unwrap();
+        assert_eq!(<(i64, i64)>::from(range), (0, 60));
+    }
+
+    #[cfg(feature = "fixed-point")]
+    #[test]
+    fn helpers_read_fixed_point_prices() {
+        use polars::prelude::NamedFrom;
+
+        let df = polars::df!(
+            "timestamp" => &[0i64, 60],
+            "high" => &[3.0, 6.0],
+            "low" => &[1.0, 4.0],
+            "close" => &[2.0, 123.45],
+            "volume" => &[100i64, 300],
+        )
+        .unwrap();
+        let df = frame::with_precision(df, PrecisionMode::Fixed(2)).unwrap();
+        assert!(df.column("close_e2").is_ok());
+        assert_eq!(last_close(&df).unwrap(), 123.45);
+        let high = frame::aggregate(&df, &Aggregation::High).unwrap();
+        assert_eq!(high, 6.0);
+        let frames = HashMap::from([("AAPL".to_string(), df)]);
+        let panel = frame::to_panel(&frames, frame::TIME_COLUMN, "close").unwrap();
+        let cents: Vec<Option<i64>> = panel
+            .column("AAPL")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(cents, vec![Some(200), Some(12345)]);
+    }
+
+    #[test]
+    fn equity_frames_are_checked_for_their_columns() {
+        use polars::prelude::NamedFrom;
+
+        let df = polars::df!("ts" => &[0i64], "c" => &[1.0]).unwrap();
+        let client = Client::new(None);
+        let daily = equity_datatype(false);
+        assert!(matches!(
+            client.postprocess(df.clone(), "AAPL", daily),
+            Err(MoabError::DecodeError)
+        ));
+        assert!(client.postprocess(df.clone(), "AAPL", "other").is_ok());
+        let client = Client::new(None).with_expected_columns(&["timestamp", "close"]);
+        assert!(client.postprocess(df.clone(), "AAPL", daily).is_ok());
+        let unchecked = Client::new(None).with_expected_columns(&[] as &[&str]);
+        assert!(unchecked.postprocess(df, "AAPL", daily).is_ok());
+    }
+
+    #[test]
+    fn short_frames_are_retried_for_recent_unfiltered_windows() {
+        let retrying = || Client::new(None).with_short_frame_retries(3, std::time::Duration::ZERO);
+        let recent = days_ago(20, 0);
+        let client = retrying();
+        assert!(client.short_frame_minimum(recent, true).unwrap() > 0.0);
+        assert!(client.short_frame_minimum(recent, false).unwrap() > 0.0);
+        assert!(client.short_frame_minimum(days_ago(20, 5), false).is_none());
+        assert!(retrying()
+            .with_row_limit(5)
+            .short_frame_minimum(recent, false)
+            .is_none());
+        // A pre-market session cuts intraday frames short, but daily data isn't filtered
+        let pre_market = retrying().with_session(Session::PreMarket, Market::Nyse);
+        assert!(pre_market.short_frame_minimum(recent, true).is_none());
+        assert!(pre_market.short_frame_minimum(recent, false).unwrap() > 0.0);
+    }
+
+    #[test]
+    fn channel_batches_send_every_ticker() {
+        let client = Client::new(None).require_credentials(true);
+        let (frames, received) = std::sync::mpsc::channel();
+        let (errors, failed) = std::sync::mpsc::channel();
+        client.get_equities_to_channel(&["AAPL", "MSFT"], days_ago(5, 0), false, frames, errors);
+        assert_eq!(received.iter().count(), 0);
+        let mut failed: Vec<String> = failed.iter().map(|(ticker, _)| ticker).collect();
+        failed.sort();
+        assert_eq!(failed, vec!["AAPL", "MSFT"]);
+    }
+
+    #[test]
+    fn plain_parquet_bodies_skip_the_wrapper() {
+        use polars::prelude::{NamedFrom, ParquetWriter};
+
+        let mut df = polars::df!("timestamp" => &[0i64, 60]).unwrap();
+        let mut data = Vec::new();
+        ParquetWriter::new(&mut data).finish(&mut df).unwrap();
+        let decoded = decode(parse_body(data).unwrap()).unwrap();
+        assert!(decoded.frame_equal(&df));
+
+        let wrapped = protocol::Response {
+            code: 404,
+            message: String::new(),
+            data: Vec::new(),
+            version: 0,
+        };
+        let body = base64::encode(prost::Message::encode_to_vec(&wrapped));
+        assert_eq!(parse_body(body.into_bytes()).unwrap().code, 404);
+    }
+
+    #[test]
+    fn raw_bodies_keep_the_server_bytes() {
+        // Not a valid Response, but exactly what the server sent
+        let payload = vec![0xff, 0x00, 0x12];
+        let body = base64::encode(&payload).into_bytes();
+        assert_eq!(raw_body(body).unwrap(), payload);
+        let parquet = b"PAR1 rest of the file".to_vec();
+        assert_eq!(raw_body(parquet.clone()).unwrap(), parquet);
+        assert!(matches!(
+            raw_body(b"<html>".to_vec()),
+            Err(MoabError::UnexpectedBody(_))
+        ));
+        assert!(matches!(
+            raw_body(b"not base64!".to_vec()),
+            Err(MoabError::DecodeError)
+        ));
+    }
+
+    #[test]
+    fn malformed_bodies_are_decode_errors() {
+        assert!(matches!(
+            parse_body(b"not base64!".to_vec()),
+            Err(MoabError::DecodeError)
+        ));
+        // Valid base64, but not a protobuf Response
+        let body = base64::encode([0xff, 0xff, 0xff]).into_bytes();
+        assert!(matches!(parse_body(body), Err(MoabError::DecodeError)));
+        assert!(matches!(
+            decode(wrap_parquet(b"PAR1 but not the rest".to_vec())),
+            Err(MoabError::DecodeError)
+        ));
+    }
+
+    #[test]
+    fn retry_after_is_seconds_or_a_date() {
+        use std::time::Duration;
+
+        assert_eq!(parse_retry_after(" 120"), Some(Duration::from_secs(120)));
+        let later = (chrono::Utc::now() + chrono::Duration::minutes(10)).to_rfc2822();
+        let wait = parse_retry_after(&later).unwrap();
+        assert!(wait > Duration::from_secs(590) && wait <= Duration::from_secs(600));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    /// The error a request to a server that accepts connections but never responds fails with
+    fn stalled_request(config: AgentConfig, overall: Option<std::time::Duration>) -> MoabError {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let mut call = config.build().get(&url);
+        if let Some(overall) = overall {
+            call = call.timeout(overall);
+        }
+        let started = Instant::now();
+        let e = call.call().unwrap_err();
+        drop(listener);
+        match timeout_phase(&e, started.elapsed(), overall) {
+            Some(phase) => MoabError::ServerTimeoutError(phase),
+            None => MoabError::TransportError,
+        }
+    }
+
+    /// Serve `responses` in turn on `listener`, returning the `x-req` header of each request
+    fn serve(
+        listener: std::net::TcpListener,
+        responses: Vec<String>,
+    ) -> std::thread::JoinHandle<Vec<Option<String>>> {
+        use std::io::{BufRead, BufReader, Write};
+
+        std::thread::spawn(move || {
+            let mut seen = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut x_req = None;
+                for line in BufReader::new(stream.try_clone().unwrap()).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("x-req") {
+                            x_req = Some(value.trim().to_string());
+                        }
+                    }
+                }
+                seen.push(x_req);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            seen
+        })
+    }
+
+    fn redirect(location: &str) -> String {
+        format!("HTTP/1.1 302 Found\r\nLocation: {location}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+    }
+
+    #[test]
+    fn redirects_keep_credentials_on_the_origin() {
+        let ok = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok".to_string();
+        let api = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        // Another loopback address, standing in for another host
+        let other = std::net::TcpListener::bind("127.0.0.2:0").unwrap();
+        let api_url = format!("http://{}/", api.local_addr().unwrap());
+        let other_url = format!("http://{}/moved", other.local_addr().unwrap());
+        let api = serve(api, vec![redirect("/moved"), redirect(&other_url)]);
+        let other = serve(other, vec![ok]);
+
+        let agent = AgentConfig::default().build();
+        let resp = call_api(&agent, &api_url, "secret", &[], Instant::now(), None).unwrap();
+        assert_eq!(resp.into_string().unwrap(), "ok");
+        let secret = Some("secret".to_string());
+        assert_eq!(api.join().unwrap(), vec![secret.clone(), secret]);
+        assert_eq!(other.join().unwrap(), vec![None]);
+    }
+
+    #[test]
+    fn redirect_loops_are_capped() {
+        let api = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_url = format!("http://{}/", api.local_addr().unwrap());
+        let loops = (0..=MAX_REDIRECTS).map(|_| redirect("/")).collect();
+        let api = serve(api, loops);
+        let agent = AgentConfig::default().build();
+        assert!(matches!(
+            call_api(&agent, &api_url, "secret", &[], Instant::now(), None),
+            Err(MoabError::TooManyRedirects)
+        ));
+        assert_eq!(api.join().unwrap().len(), MAX_REDIRECTS as usize + 1);
+    }
+
+    #[test]
+    fn timeouts_report_their_phase() {
+        use std::time::Duration;
+
+        let config = AgentConfig {
+            first_byte_timeout: Some(Duration::from_millis(100)),
+            ..AgentConfig::default()
+        };
+        assert!(matches!(
+            stalled_request(config, None),
+            MoabError::ServerTimeoutError(TimeoutPhase::FirstByte)
+        ));
+        let overall = Some(Duration::from_millis(100));
+        assert!(matches!(
+            stalled_request(AgentConfig::default(), overall),
+            MoabError::ServerTimeoutError(TimeoutPhase::Overall)
+        ));
+    }
+
+    #[test]
+    fn timeouts_trip_the_circuit() {
+        let circuit = CircuitBreaker::new(2, std::time::Duration::ZERO);
+        let timeout: Result<(), _> = Err(MoabError::ServerTimeoutError(TimeoutPhase::FirstByte));
+        circuit
+            .allow()
+            .unwrap()
+            .record(trips_circuit(None, &timeout));
+        circuit
+            .allow()
+            .unwrap()
+            .record(trips_circuit(None, &timeout));
+        assert!(circuit.is_open());
+        // A probe that times out keeps it open
+        let probe = circuit.allow().unwrap();
+        let timeout: Result<(), _> = Err(MoabError::ServerTimeoutError(TimeoutPhase::Connect));
+        probe.record(trips_circuit(None, &timeout));
+        assert!(circuit.is_open());
+        assert!(!trips_circuit(Some(MAINTENANCE_CODE), &Ok(())));
+    }
+
+    #[test]
+    fn detects_error_pages() {
+        assert!(looks_like_error_page(
+            b"\n<html><body>502 Bad Gateway</body></html>"
+        ));
+        assert!(looks_like_error_page(b"{\"error\": \"unavailable\"}"));
+        assert!(!looks_like_error_page(b"CMgBGgA="));
+    }
+
+    #[test]
+    fn snippet_is_truncated_and_lossy() {
+        let body = [vec![0xff], vec![b'a'; 1000]].concat();
+        let snippet = body_snippet(&body);
+        assert!(snippet.starts_with('\u{fffd}'));
+        assert_eq!(
+            snippet.len(),
+            '\u{fffd}'.len_utf8() + BODY_SNIPPET_BYTES - 1
+        );
+    }
+}
@@ -0,0 +1,156 @@
+// Jackson Coxson
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+};
+
+/// Shares the result of a call between every caller that makes the same call while it's in flight
+pub(crate) struct Coalescer<K, V> {
+    in_flight: Mutex<HashMap<K, Arc<Flight<V>>>>,
+}
+
+struct Flight<V> {
+    state: Mutex<State<V>>,
+    done: Condvar,
+}
+
+enum State<V> {
+    Running,
+    Done(V),
+    /// The call panicked, so every waiter makes it again itself
+    Abandoned,
+}
+
+/// Finishes the flight when the caller making the call returns or panics
+struct Leader<'a, K: Eq + Hash, V> {
+    coalescer: &'a Coalescer<K, V>,
+    key: K,
+    flight: Arc<Flight<V>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(e) => e.into_inner(),
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Coalescer<K, V> {
+    pub(crate) fn new() -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Make the call with `f`, unless a call with the same key is already in flight,
+    /// in which case wait for it and return a clone of its result
+    pub(crate) fn run(&self, key: K, f: impl FnOnce() -> V) -> V {
+        let mut in_flight = lock(&self.in_flight);
+        if let Some(flight) = in_flight.get(&key).cloned() {
+            drop(in_flight);
+            return match flight.wait() {
+                Some(value) => value,
+                None => f(),
+            };
+        }
+        let flight = Arc::new(Flight {
+            state: Mutex::new(State::Running),
+            done: Condvar::new(),
+        });
+        in_flight.insert(key.clone(), flight.clone());
+        drop(in_flight);
+
+        let leader = Leader {
+            coalescer: self,
+            key,
+            flight,
+        };
+        let value = f();
+        *lock(&leader.flight.state) = State::Done(value.clone());
+        value
+    }
+}
+
+impl<V: Clone> Flight<V> {
+    /// Wait for the call to finish, returning None if it panicked
+    fn wait(&self) -> Option<V> {
+        let mut state = lock(&self.state);
+        loop {
+            match &*state {
+                State::Done(value) => return Some(value.clone()),
+                State::Abandoned => return None,
+                State::Running => {
+                    state = match self.done.wait(state) {
+                        Ok(state) => state,
+                        Err(e) => e.into_inner(),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for Leader<'_, K, V> {
+    fn drop(&mut self) {
+        lock(&self.coalescer.in_flight).remove(&self.key);
+        let mut state = lock(&self.flight.state);
+        if let State::Running = *state {
+            *state = State::Abandoned;
+        }
+        self.flight.done.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    #[test]
+    fn identical_calls_share_one_result() {
+        let coalescer = Coalescer::new();
+        let calls = AtomicUsize::new(0);
+        let call = || {
+            calls.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(200));
+            42
+        };
+        std::thread::scope(|scope| {
+            let leader = scope.spawn(|| coalescer.run("AAPL", call));
+            std::thread::sleep(Duration::from_millis(50));
+            let waiters: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| coalescer.run("AAPL", call)))
+                .collect();
+            assert_eq!(leader.join().unwrap(), 42);
+            for waiter in waiters {
+                assert_eq!(waiter.join().unwrap(), 42);
+            }
+        });
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        // Nothing is in flight anymore, so the next call is made again
+        coalescer.run("AAPL", call);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn waiters_retry_after_a_panic() {
+        let coalescer = Coalescer::new();
+        std::thread::scope(|scope| {
+            let leader = scope.spawn(|| {
+                coalescer.run("AAPL", || {
+                    std::thread::sleep(Duration::from_millis(200));
+                    panic!("request panicked")
+                })
+            });
+            std::thread::sleep(Duration::from_millis(50));
+            let waiter = scope.spawn(|| coalescer.run("AAPL", || 7));
+            assert!(leader.join().is_err());
+            assert_eq!(waiter.join().unwrap(), 7);
+        });
+    }
+}
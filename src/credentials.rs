@@ -1,11 +1,18 @@
 // Jackson Coxson
 
+use std::str::FromStr;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Credentials {
     pub username: String,
     pub token: String,
 }
 
+/// The keyring account that holds the username for a service.
+/// The token itself is stored under the username's account.
+#[cfg(feature = "keyring")]
+const KEYRING_USERNAME_ACCOUNT: &str = "moabdb-username";
+
 impl Credentials {
     pub fn new(username: impl AsRef<str>, token: impl AsRef<str>) -> Self {
         Self {
@@ -13,4 +20,85 @@ impl Credentials {
             token: token.as_ref().to_string(),
         }
     }
+
+    /// Load the credentials from the OS secret store
+    /// # Arguments
+    /// * `service` - The keyring service the credentials were saved under
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use moabdb::credentials::Credentials;
+    ///
+    /// let creds = Credentials::from_keyring("moabdb").unwrap();
+    /// ```
+    #[cfg(feature = "keyring")]
+    pub fn from_keyring(service: impl AsRef<str>) -> Result<Self, String> {
+        let service = service.as_ref();
+        let username = match keyring::Entry::new(service, KEYRING_USERNAME_ACCOUNT) {
+            Ok(entry) => match entry.get_password() {
+                Ok(username) => username,
+                Err(e) => return Err(e.to_string()),
+            },
+            Err(e) => return Err(e.to_string()),
+        };
+        let token = match keyring::Entry::new(service, &username) {
+            Ok(entry) => match entry.get_password() {
+                Ok(token) => token,
+                Err(e) => return Err(e.to_string()),
+            },
+            Err(e) => return Err(e.to_string()),
+        };
+        Ok(Self { username, token })
+    }
+
+    /// Save the credentials to the OS secret store, to be loaded later with `from_keyring`
+    /// # Arguments
+    /// * `service` - The keyring service to save the credentials under
+    #[cfg(feature = "keyring")]
+    pub fn save_to_keyring(&self, service: impl AsRef<str>) -> Result<(), String> {
+        let service = service.as_ref();
+        let username = match keyring::Entry::new(service, KEYRING_USERNAME_ACCOUNT) {
+            Ok(entry) => entry,
+            Err(e) => return Err(e.to_string()),
+        };
+        let token = match keyring::Entry::new(service, &self.username) {
+            Ok(entry) => entry,
+            Err(e) => return Err(e.to_string()),
+        };
+        if let Err(e) = username.set_password(&self.username) {
+            return Err(e.to_string());
+        }
+        if let Err(e) = token.set_password(&self.token) {
+            return Err(e.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Parse credentials from a `username:token` string, such as one passed on the command line
+/// # Examples
+/// ```
+/// use moabdb::credentials::Credentials;
+///
+/// let creds: Credentials = "user:token".parse().unwrap();
+/// assert_eq!(creds, Credentials::new("user", "token"));
+/// assert!("user".parse::<Credentials>().is_err());
+/// assert!(":token".parse::<Credentials>().is_err());
+/// ```
+impl FromStr for Credentials {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (username, token) = match s.split_once(':') {
+            Some(parts) => parts,
+            None => return Err("Credentials must be formatted as username:token".to_string()),
+        };
+        if username.is_empty() {
+            return Err("Username must not be empty".to_string());
+        }
+        if token.is_empty() {
+            return Err("Token must not be empty".to_string());
+        }
+        Ok(Self::new(username, token))
+    }
 }
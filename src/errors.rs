@@ -1,13 +1,40 @@
 // Jackson Coxson
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum MoabError {
     ServerInternalError,
-    ServerTimeoutError,
+    ServerTimeoutError(TimeoutPhase),
     DecodeError,
+    WriteError,
     RequestError,
     TransportError,
+    UnexpectedBody(String),
+    UnsupportedProtocol(u32),
+    TooManyRedirects,
+    CircuitOpen,
+    /// The API is down for scheduled maintenance. `retry_after` is how long the server suggested
+    /// waiting before trying again, if it said.
+    Maintenance {
+        retry_after: Option<std::time::Duration>,
+    },
+    ClockSkew,
     NotFound,
     Unauthorized,
-    UnknownError,
+    AuthenticationRequired,
+    /// A status code the crate doesn't recognize, with the server's message. The code is 0 for
+    /// a failure that isn't from the server, such as a batch worker thread panicking.
+    UnknownError(u32, String),
+}
+
+/// The part of a request that took too long, see `Client::with_connect_timeout`,
+/// `Client::with_first_byte_timeout` and `Client::with_overall_timeout`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// Connecting to the API took longer than the connect timeout
+    Connect,
+    /// The server sent nothing for longer than the first byte timeout, either before the
+    /// response started or between reads of it
+    FirstByte,
+    /// The whole request, from connecting to reading the last byte, took longer than the overall timeout
+    Overall,
 }
@@ -0,0 +1,1497 @@
+// Jackson Coxson
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+};
+
+use chrono::{DateTime, Datelike, NaiveDateTime, Weekday};
+use polars::prelude::{
+    AnyValue, BooleanChunked, ChunkAgg, CsvWriter, DataFrame, DataType, FillNullStrategy, IdxCa,
+    IdxSize, NamedFrom, SerWriter, Series, TimeUnit, UniqueKeepStrategy,
+};
+use polars::series::IsSorted;
+
+use crate::{
+    errors::MoabError,
+    window::{Bound, Market, Session, Window, WindowLength},
+};
+
+/// The name of the timestamp column in frames returned by the server
+pub const TIME_COLUMN: &str = "timestamp";
+
+/// The default name of the column `tag_symbol` adds, and the column naming the rows of `correlation`
+pub const SYMBOL_COLUMN: &str = "symbol";
+
+/// The renames `canonicalize_columns` applies, from the name used by some datatype to the
+/// canonical name. Columns not listed keep their name.
+pub const CANONICAL_COLUMNS: &[(&str, &str)] = &[
+    ("ts", "timestamp"),
+    ("time", "timestamp"),
+    ("datetime", "timestamp"),
+    ("o", "open"),
+    ("h", "high"),
+    ("l", "low"),
+    ("c", "close"),
+    ("vol", "volume"),
+    ("v", "volume"),
+];
+
+/// The columns of the server's `daily_stocks` and `intraday_stocks` frames, by their canonical
+/// names, which `Client` checks equity frames for unless told otherwise, see `check_columns`
+pub const EQUITY_COLUMNS: &[&str] = &["timestamp", "open", "high", "low", "close", "volume"];
+
+/// The most trading days of bars a frame can be missing at the end of its window before
+/// `coverage` says it ends early, enough to cover a long holiday weekend
+const ENDS_EARLY_TRADING_DAYS: usize = 3;
+
+/// The price columns `scale_prices` divides
+pub const PRICE_COLUMNS: &[&str] = &["open", "high", "low", "close"];
+
+/// The number of rows written between flushes by `write_csv`
+const CSV_BATCH_ROWS: usize = 65_536;
+
+/// The column `fill_gaps` marks the rows it was given with while joining
+const FILL_MARKER: &str = "__moabdb_fill_gaps_present";
+
+/// How the rows inserted by `fill_gaps` are filled
+pub enum FillStrategy {
+    /// Repeat the previous bar's values
+    Forward,
+    /// Leave every value in the inserted rows null
+    Null,
+    /// Repeat the previous bar's values, but set the volume to zero
+    ZeroVolume,
+}
+
+/// The kind of returns computed by `add_returns`
+pub enum ReturnKind {
+    /// `p[t] / p[t - 1] - 1`
+    Simple,
+    /// `ln(p[t] / p[t - 1])`
+    Log,
+}
+
+/// A single value computed from a frame of bars by `aggregate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Aggregation {
+    /// The volume weighted average of each bar's typical price, `(high + low + close) / 3`
+    Vwap,
+    /// The mean volume per bar
+    MeanVolume,
+    /// The total volume traded
+    TotalVolume,
+    /// The highest high
+    High,
+    /// The lowest low
+    Low,
+    /// The mean of the named column
+    Mean(String),
+}
+
+/// How much of a requested window a frame covers, see `coverage`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Coverage {
+    /// The time of the frame's last bar, truncated to the second, None if it has no bars
+    pub last_available: Option<NaiveDateTime>,
+    /// Whether the bars stop well before the end of the window, as they do for a symbol that
+    /// was delisted or halted during it. A frame without bars doesn't end early.
+    pub ends_early: bool,
+}
+
+/// The type of the price columns in decoded frames, see `with_precision`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PrecisionMode {
+    /// Keep the columns as decoded, 64 bit floats with about 15 significant digits
+    #[default]
+    F64,
+    /// Cast the columns to 32 bit floats, halving their memory but keeping only about
+    /// 7 significant digits, enough for most prices but not for large cumulative values
+    F32,
+    /// Convert the `PRICE_COLUMNS` to exact fixed point: 64 bit integers counting units of
+    /// `10^-decimals`, so `Fixed(2)` gives whole cents. Each price is rounded to the nearest unit,
+    /// which recovers the exact value of any price with at most that many decimals. Sums and
+    /// differences of the integers are then exact. Other float columns are left alone.
+    ///
+    /// polars 0.25 has no `Decimal` type, so the scale can't be carried by the column's type.
+    /// It's carried by the name instead: each converted column is renamed `<name>_e<decimals>`,
+    /// so with `Fixed(2)` the `close` column becomes `close_e2`, see `fixed_point_column`.
+    /// Divide by `10^decimals` to get prices back. The helpers that look up price columns by
+    /// name, such as `aggregate` and `to_panel`, find the renamed columns too.
+    #[cfg(feature = "fixed-point")]
+    Fixed(u32),
+}
+
+/// The direction `apply_output` sorts rows by time in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Oldest first
+    Ascending,
+    /// Newest first
+    Descending,
+}
+
+/// The column order and row order of a frame, applied by `apply_output`.
+/// The default keeps the frame's own order for both.
+/// # Examples
+/// ```rust
+/// use moabdb::frame::{OutputSpec, SortOrder};
+///
+/// let spec = OutputSpec::new()
+///     .columns(&["timestamp", "close"])
+///     .sort(SortOrder::Descending);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputSpec {
+    /// The columns to put first, in order. The frame's other columns follow in their own order
+    pub columns: Option<Vec<String>>,
+    /// The direction to sort rows by time in
+    pub sort: Option<SortOrder>,
+}
+
+impl OutputSpec {
+    /// Create an OutputSpec that keeps the frame's order
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Put `columns` first, in this order. Columns the frame doesn't have are skipped
+    pub fn columns(mut self, columns: &[impl AsRef<str>]) -> Self {
+        self.columns = Some(columns.iter().map(|c| c.as_ref().to_string()).collect());
+        self
+    }
+    /// Sort rows by time in `order`
+    pub fn sort(mut self, order: SortOrder) -> Self {
+        self.sort = Some(order);
+        self
+    }
+}
+
+/// Fill the missing bars in a time series so they sit on a regular grid
+/// # Arguments
+/// * `df` - The frame to fill, as returned by `get_equity`
+/// * `time_col` - The name of the timestamp column. It must be a datetime or epoch seconds
+/// * `granularity` - The spacing of the bars
+/// * `fill` - How to fill the inserted rows
+/// * `session` - The part of `market`'s trading day intraday bars may be inserted in
+/// * `market` - The market whose hours and time zone intraday bars are checked against
+///
+/// # Returns
+/// The frame reindexed to a complete grid of timestamps between its first and last bar.
+/// For intraday granularities bars are only inserted in `session`, between bars of the same
+/// local day, so overnight gaps and gaps outside the session are left alone. For daily or longer
+/// granularities weekends are skipped. Only the inserted rows are filled; a null the frame
+/// already had stays null.
+///
+/// # Examples
+/// ```rust
+/// use moabdb::frame::{fill_gaps, FillStrategy};
+/// use moabdb::window::{Market, Session, WindowLength};
+/// use polars::prelude::*;
+///
+/// // 9:30 to 9:34 New York time, with 9:32 missing and no close at 9:33
+/// let open = 1_688_391_000i64;
+/// let df = df!(
+///     "time" => &[open, open + 60, open + 180, open + 240],
+///     "close" => &[Some(1.0), Some(2.0), None, Some(4.0)],
+/// ).unwrap();
+/// let df = fill_gaps(
+///     &df,
+///     "time",
+///     WindowLength::Minutes(1),
+///     FillStrategy::Forward,
+///     Session::Regular,
+///     Market::Nyse,
+/// )
+/// .unwrap();
+/// let close: Vec<Option<f64>> = df.column("close").unwrap().f64().unwrap().into_iter().collect();
+/// assert_eq!(close, vec![Some(1.0), Some(2.0), Some(2.0), None, Some(4.0)]);
+///
+/// // After hours bars at 16:00 and 16:05 are left alone for the regular session
+/// let df = df!("time" => &[open + 23_400, open + 23_700], "close" => &[1.0, 2.0]).unwrap();
+/// let df = fill_gaps(
+///     &df,
+///     "time",
+///     WindowLength::Minutes(1),
+///     FillStrategy::Forward,
+///     Session::Regular,
+///     Market::Nyse,
+/// )
+/// .unwrap();
+/// assert_eq!(df.height(), 2);
+/// ```
+pub fn fill_gaps(
+    df: &DataFrame,
+    time_col: &str,
+    granularity: WindowLength,
+    fill: FillStrategy,
+    session: Session,
+    market: Market,
+) -> Result<DataFrame, MoabError> {
+    let time = match df.column(time_col) {
+        Ok(time) => time,
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    let dtype = time.dtype().clone();
+    let units_per_second = match units_per_second(&dtype) {
+        Some(units) => units,
+        None => return Err(MoabError::DecodeError),
+    };
+    let granularity = match granularity.duration() {
+        Some(granularity) => granularity,
+        None => return Err(MoabError::RequestError),
+    };
+    let step = match granularity.num_seconds().checked_mul(units_per_second) {
+        Some(step) if step > 0 => step,
+        _ => return Err(MoabError::RequestError),
+    };
+    let intraday = granularity < chrono::Duration::days(1);
+
+    let mut existing: Vec<i64> = match time.cast(&DataType::Int64) {
+        Ok(time) => match time.i64() {
+            Ok(time) => time.into_iter().flatten().collect(),
+            Err(_) => return Err(MoabError::DecodeError),
+        },
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    existing.sort_unstable();
+    existing.dedup();
+
+    let to_datetime = |t: i64| -> Option<NaiveDateTime> {
+        DateTime::from_timestamp(t.div_euclid(units_per_second), 0).map(|dt| dt.naive_utc())
+    };
+    let local_date =
+        |utc: NaiveDateTime| (utc + chrono::Duration::hours(market.utc_offset(utc.date()))).date();
+
+    let mut grid = Vec::with_capacity(existing.len());
+    for pair in existing.windows(2) {
+        let (prev, next) = (pair[0], pair[1]);
+        grid.push(prev);
+        let prev_dt = to_datetime(prev);
+        let mut t = prev.saturating_add(step);
+        while t < next {
+            let keep = match (prev_dt, to_datetime(t)) {
+                (Some(prev_dt), Some(dt)) if intraday => {
+                    local_date(prev_dt) == local_date(dt) && session.contains(dt, market)
+                }
+                (_, Some(dt)) => !matches!(dt.weekday(), Weekday::Sat | Weekday::Sun),
+                _ => false,
+            };
+            if keep {
+                grid.push(t);
+            }
+            t = t.saturating_add(step);
+        }
+    }
+    if let Some(last) = existing.last() {
+        grid.push(*last);
+    }
+
+    let grid = match Series::new(time_col, grid).cast(&dtype) {
+        Ok(grid) => grid,
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    let grid = match DataFrame::new(vec![grid]) {
+        Ok(grid) => grid,
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    // Mark the frame's own rows, so the inserted ones can be told apart from its nulls
+    let mut marked = df.clone();
+    if marked
+        .with_column(Series::new(FILL_MARKER, vec![true; df.height()]))
+        .is_err()
+    {
+        return Err(MoabError::DecodeError);
+    }
+    let mut filled = match grid.left_join(&marked, [time_col], [time_col]) {
+        Ok(filled) => filled,
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    let present = match filled.drop_in_place(FILL_MARKER) {
+        Ok(marker) => marker.is_not_null(),
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    // Each inserted row repeats the last row the frame had before it
+    let mut last = None;
+    let sources: IdxCa = present
+        .into_iter()
+        .enumerate()
+        .map(|(row, present)| {
+            if present == Some(true) {
+                last = IdxSize::try_from(row).ok();
+            }
+            last
+        })
+        .collect();
+
+    let columns: Vec<String> = filled
+        .get_column_names()
+        .iter()
+        .filter(|name| **name != time_col)
+        .map(|name| name.to_string())
+        .collect();
+    for name in columns {
+        let zero = match fill {
+            FillStrategy::Null => continue,
+            FillStrategy::Forward => false,
+            FillStrategy::ZeroVolume => name.eq_ignore_ascii_case("volume"),
+        };
+        let result = filled.try_apply(&name, |s| {
+            if zero {
+                s.zip_with(&present, &s.fill_null(FillNullStrategy::Zero)?)
+            } else {
+                s.take(&sources)
+            }
+        });
+        if result.is_err() {
+            return Err(MoabError::DecodeError);
+        }
+    }
+
+    Ok(filled)
+}
+
+/// Sort a frame by its timestamp column, ascending, and drop exact duplicate rows.
+/// Rows are only dropped when every column matches, so two different bars that share a
+/// timestamp are both kept.
+/// # Examples
+/// ```rust
+/// use moabdb::frame::sort_and_dedup;
+/// use polars::prelude::*;
+///
+/// let df = df!(
+///     "timestamp" => &[60i64, 0, 60],
+///     "close" => &[2.0, 1.0, 2.0],
+/// ).unwrap();
+/// let df = sort_and_dedup(&df, "timestamp").unwrap();
+/// assert_eq!(df.height(), 2);
+/// ```
+pub fn sort_and_dedup(df: &DataFrame, time_col: &str) -> Result<DataFrame, MoabError> {
+    let sorted = match df.sort([time_col], false) {
+        Ok(sorted) => sorted,
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    match sorted.unique_stable(None, UniqueKeepStrategy::First) {
+        Ok(deduped) => Ok(deduped),
+        Err(_) => Err(MoabError::DecodeError),
+    }
+}
+
+/// Write a frame to `writer` as CSV in batches, flushing after each one,
+/// so the CSV text for the whole frame is never held in memory at once.
+/// # Returns
+/// The number of rows written, not counting the header
+///
+/// # Examples
+/// ```rust
+/// use moabdb::frame::write_csv;
+/// use polars::prelude::*;
+///
+/// let df = df!("timestamp" => &[0i64, 60], "close" => &[1.0, 2.0]).unwrap();
+/// let mut out = Vec::new();
+/// assert_eq!(write_csv(&df, &mut out).unwrap(), 2);
+/// assert!(String::from_utf8(out).unwrap().starts_with("timestamp,close"));
+/// ```
+pub fn write_csv(df: &DataFrame, writer: &mut impl Write) -> Result<usize, MoabError> {
+    let mut offset = 0;
+    while offset == 0 || offset < df.height() {
+        let mut batch = df.slice(offset as i64, CSV_BATCH_ROWS);
+        if CsvWriter::new(&mut *writer)
+            .has_header(offset == 0)
+            .finish(&mut batch)
+            .is_err()
+        {
+            return Err(MoabError::WriteError);
+        }
+        if writer.flush().is_err() {
+            return Err(MoabError::WriteError);
+        }
+        offset += CSV_BATCH_ROWS;
+    }
+    Ok(df.height())
+}
+
+/// Write a frame to `writer` in the Arrow IPC file format, also known as Feather v2.
+/// The file is uncompressed, so it can be memory mapped and read back without copying,
+/// and it reads back with exactly the frame's schema.
+/// # Returns
+/// The number of rows written
+///
+/// # Examples
+/// ```rust
+/// use moabdb::frame::write_ipc;
+/// use polars::prelude::*;
+///
+/// let df = df!("timestamp" => &[0i64, 60], "close" => &[1.0, 2.0]).unwrap();
+/// let mut out = Vec::new();
+/// assert_eq!(write_ipc(&df, &mut out).unwrap(), 2);
+/// let read = IpcReader::new(std::io::Cursor::new(out)).finish().unwrap();
+/// assert_eq!(read.schema(), df.schema());
+/// assert!(read.frame_equal(&df));
+/// ```
+#[cfg(feature = "ipc")]
+pub fn write_ipc(df: &DataFrame, writer: &mut impl Write) -> Result<usize, MoabError> {
+    use polars::prelude::IpcWriter;
+
+    let mut df = df.clone();
+    if IpcWriter::new(&mut *writer)
+        .with_compression(None)
+        .finish(&mut df)
+        .is_err()
+    {
+        return Err(MoabError::WriteError);
+    }
+    match writer.flush() {
+        Ok(_) => Ok(df.height()),
+        Err(_) => Err(MoabError::WriteError),
+    }
+}
+
+/// Convert a frame to one JSON object per row, keyed by column name, without going through
+/// polars' JSON writer. Integers and floats become JSON numbers, with NaN and infinite floats
+/// becoming null as JSON has no way to write them. Nulls stay null, strings and booleans keep
+/// their type, and `Datetime` columns become RFC 3339 strings in UTC. Any other type is written
+/// as its string form.
+/// # Examples
+/// ```rust
+/// use moabdb::frame::{to_json_rows, to_time_index};
+/// use polars::prelude::*;
+///
+/// let df = df!(
+///     "timestamp" => &[0i64, 60],
+///     "close" => &[Some(1.5), None],
+///     "open" => &[f32::NAN, 2.0],
+///     "volume" => &[10u32, 20],
+/// )
+/// .unwrap();
+/// let rows = to_json_rows(&df).unwrap();
+/// assert_eq!(rows[0], serde_json::json!({"timestamp": 0, "close": 1.5, "open": null, "volume": 10}));
+/// assert_eq!(rows[1], serde_json::json!({"timestamp": 60, "close": null, "open": 2.0, "volume": 20}));
+///
+/// let df = to_time_index(df, "timestamp").unwrap();
+/// assert_eq!(to_json_rows(&df).unwrap()[1]["timestamp"], "1970-01-01T00:01:00Z");
+/// ```
+#[cfg(feature = "json")]
+pub fn to_json_rows(df: &DataFrame) -> Result<Vec<serde_json::Value>, MoabError> {
+    use polars::prelude::AnyValue;
+    use serde_json::{Map, Number, Value};
+
+    let float = |f: f64| Number::from_f64(f).map_or(Value::Null, Value::Number);
+    let mut rows = vec![Map::new(); df.height()];
+    for col in df.get_columns() {
+        let col = col.rechunk();
+        for (row, value) in rows.iter_mut().zip(col.iter()) {
+            let value = match value {
+                AnyValue::Null => Value::Null,
+                AnyValue::Boolean(b) => Value::Bool(b),
+                AnyValue::Utf8(s) => Value::String(s.to_string()),
+                AnyValue::Utf8Owned(s) => Value::String(s.to_string()),
+                AnyValue::UInt8(n) => n.into(),
+                AnyValue::UInt16(n) => n.into(),
+                AnyValue::UInt32(n) => n.into(),
+                AnyValue::UInt64(n) => n.into(),
+                AnyValue::Int8(n) => n.into(),
+                AnyValue::Int16(n) => n.into(),
+                AnyValue::Int32(n) => n.into(),
+                AnyValue::Int64(n) => n.into(),
+                AnyValue::Float32(f) => float(f64::from(f)),
+                AnyValue::Float64(f) => float(f),
+                AnyValue::Datetime(t, unit, _) => {
+                    let datetime = match unit {
+                        TimeUnit::Milliseconds => DateTime::from_timestamp_millis(t),
+                        TimeUnit::Microseconds => DateTime::from_timestamp_micros(t),
+                        TimeUnit::Nanoseconds => Some(DateTime::from_timestamp_nanos(t)),
+                    };
+                    match datetime {
+                        Some(datetime) => Value::String(
+                            datetime.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true),
+                        ),
+                        None => return Err(MoabError::DecodeError),
+                    }
+                }
+                other => Value::String(other.to_string()),
+            };
+            row.insert(col.name().to_string(), value);
+        }
+    }
+    Ok(rows.into_iter().map(Value::Object).collect())
+}
+
+/// Append a column of returns computed from `col`.
+/// The new column is named `{col}_return` for simple returns or `{col}_log_return` for log returns.
+/// # Arguments
+/// * `df` - The frame to add the returns to
+/// * `kind` - Whether to compute simple or log returns
+/// * `col` - The price column to compute returns from, such as the close
+/// * `periods_per_year` - If set, annualize the returns assuming this many bars per year, such as 252 for daily bars
+///
+/// # Returns
+/// The frame with the returns column appended. The first row, and any row where either
+/// price is null or the previous price is zero, has a null return.
+///
+/// # Examples
+/// ```rust
+/// use moabdb::frame::{add_returns, ReturnKind};
+/// use polars::prelude::*;
+///
+/// let df = df!("close" => &[100.0, 110.0, 99.0]).unwrap();
+/// let df = add_returns(&df, ReturnKind::Simple, "close", None).unwrap();
+/// let returns: Vec<Option<f64>> = df.column("close_return").unwrap().f64().unwrap().into_iter().collect();
+/// assert_eq!(returns[0], None);
+/// assert!((returns[1].unwrap() - 0.1).abs() < 1e-12);
+/// ```
+pub fn add_returns(
+    df: &DataFrame,
+    kind: ReturnKind,
+    col: &str,
+    periods_per_year: Option<f64>,
+) -> Result<DataFrame, MoabError> {
+    let prices = f64_values(df, col)?;
+
+    let mut returns = Vec::with_capacity(prices.len());
+    returns.push(None);
+    for pair in prices.windows(2) {
+        let r = match (pair[0], pair[1]) {
+            (Some(prev), Some(next)) if prev != 0.0 => {
+                let r = match kind {
+                    ReturnKind::Simple => next / prev - 1.0,
+                    ReturnKind::Log => (next / prev).ln(),
+                };
+                match (&kind, periods_per_year) {
+                    (ReturnKind::Simple, Some(n)) => Some((1.0 + r).powf(n) - 1.0),
+                    (ReturnKind::Log, Some(n)) => Some(r * n),
+                    (_, None) => Some(r),
+                }
+            }
+            _ => None,
+        };
+        returns.push(r);
+    }
+    returns.truncate(prices.len());
+
+    let name = match kind {
+        ReturnKind::Simple => format!("{col}_return"),
+        ReturnKind::Log => format!("{col}_log_return"),
+    };
+    let mut df = df.clone();
+    match df.with_column(Series::new(&name, returns)) {
+        Ok(_) => Ok(df),
+        Err(_) => Err(MoabError::DecodeError),
+    }
+}
+
+/// Combine one column from several frames into a single wide frame keyed by timestamp.
+/// Each frame's `value_col` becomes a column named after its key, in sorted key order.
+/// Frames are outer joined, so a timestamp missing from one frame is null in its column.
+/// A price column converted by `PrecisionMode::Fixed` is found by its usual name, and its
+/// scaled integers are kept as they are.
+/// # Examples
+/// ```rust
+/// use std::collections::HashMap;
+/// use moabdb::frame::to_panel;
+/// use polars::prelude::*;
+///
+/// let mut frames = HashMap::new();
+/// frames.insert("AAPL".to_string(), df!("timestamp" => &[0i64, 60], "close" => &[1.0, 2.0]).unwrap());
+/// frames.insert("MSFT".to_string(), df!("timestamp" => &[60i64, 120], "close" => &[3.0, 4.0]).unwrap());
+/// let panel = to_panel(&frames, "timestamp", "close").unwrap();
+/// assert_eq!(panel.get_column_names(), &["timestamp", "AAPL", "MSFT"]);
+/// assert_eq!(panel.height(), 3);
+/// ```
+pub fn to_panel(
+    frames: &HashMap<String, DataFrame>,
+    time_col: &str,
+    value_col: &str,
+) -> Result<DataFrame, MoabError> {
+    let mut tickers: Vec<&String> = frames.keys().collect();
+    tickers.sort();
+
+    let mut panel: Option<DataFrame> = None;
+    for ticker in tickers {
+        let value_col = match resolve_column(&frames[ticker], value_col) {
+            Some((name, _)) => name,
+            None => return Err(MoabError::DecodeError),
+        };
+        let mut column = match frames[ticker].select([time_col, value_col.as_str()]) {
+            Ok(column) => column,
+            Err(_) => return Err(MoabError::DecodeError),
+        };
+        if column.rename(&value_col, ticker).is_err() {
+            return Err(MoabError::DecodeError);
+        }
+        panel = match panel {
+            Some(panel) => match panel.outer_join(&column, [time_col], [time_col]) {
+                Ok(panel) => Some(panel),
+                Err(_) => return Err(MoabError::DecodeError),
+            },
+            None => Some(column),
+        };
+    }
+
+    match panel {
+        Some(panel) => match panel.sort([time_col], false) {
+            Ok(panel) => Ok(panel),
+            Err(_) => Err(MoabError::DecodeError),
+        },
+        None => Ok(DataFrame::default()),
+    }
+}
+
+/// Compute the correlation matrix of the columns of a panel, such as one from `to_panel`.
+/// Each column's returns are computed with `add_returns` and correlated pairwise:
+/// every pair uses the rows where both returns are present, so one short history doesn't
+/// shrink the sample used for every other pair. A pair with fewer than two shared returns,
+/// or with a constant return, has a null correlation.
+/// # Returns
+/// An NxN frame with a `SYMBOL_COLUMN` column naming each row, followed by one column per symbol
+///
+/// # Examples
+/// ```rust
+/// use moabdb::frame::correlation;
+/// use polars::prelude::*;
+///
+/// let panel = df!(
+///     "timestamp" => &[0i64, 1, 2, 3],
+///     "AAPL" => &[1.0, 2.0, 1.0, 2.0],
+///     "MSFT" => &[2.0, 4.0, 2.0, 4.0],
+/// ).unwrap();
+/// let corr = correlation(&panel, "timestamp").unwrap();
+/// let aapl_msft = corr.column("MSFT").unwrap().f64().unwrap().get(0).unwrap();
+/// assert!((aapl_msft - 1.0).abs() < 1e-12);
+/// ```
+pub fn correlation(panel: &DataFrame, time_col: &str) -> Result<DataFrame, MoabError> {
+    let symbols: Vec<String> = panel
+        .get_column_names()
+        .iter()
+        .filter(|name| **name != time_col)
+        .map(|name| name.to_string())
+        .collect();
+
+    let mut returns = Vec::with_capacity(symbols.len());
+    for symbol in &symbols {
+        let df = add_returns(panel, ReturnKind::Simple, symbol, None)?;
+        returns.push(f64_values(&df, &format!("{symbol}_return"))?);
+    }
+
+    let mut columns = vec![Series::new(SYMBOL_COLUMN, &symbols)];
+    for (i, symbol) in symbols.iter().enumerate() {
+        let corr: Vec<Option<f64>> = returns
+            .iter()
+            .map(|other| pearson(&returns[i], other))
+            .collect();
+        columns.push(Series::new(symbol, corr));
+    }
+    match DataFrame::new(columns) {
+        Ok(corr) => Ok(corr),
+        Err(_) => Err(MoabError::DecodeError),
+    }
+}
+
+/// The number of time column units in a second, for a datetime or epoch seconds column
+fn units_per_second(dtype: &DataType) -> Option<i64> {
+    match dtype {
+        DataType::Datetime(TimeUnit::Milliseconds, _) => Some(1_000),
+        DataType::Datetime(TimeUnit::Microseconds, _) => Some(1_000_000),
+        DataType::Datetime(TimeUnit::Nanoseconds, _) => Some(1_000_000_000),
+        DataType::Int32 | DataType::Int64 | DataType::UInt32 | DataType::UInt64 => Some(1),
+        _ => None,
+    }
+}
+
+/// The Pearson correlation over the rows where both values are present
+fn pearson(a: &[Option<f64>], b: &[Option<f64>]) -> Option<f64> {
+    let pairs: Vec<(f64, f64)> = a
+        .iter()
+        .zip(b)
+        .filter_map(|(a, b)| Some(((*a)?, (*b)?)))
+        .collect();
+    if pairs.len() < 2 {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let mean_a = pairs.iter().map(|(a, _)| a).sum::<f64>() / n;
+    let mean_b = pairs.iter().map(|(_, b)| b).sum::<f64>() / n;
+    let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (a, b) in &pairs {
+        cov += (a - mean_a) * (b - mean_b);
+        var_a += (a - mean_a).powi(2);
+        var_b += (b - mean_b).powi(2);
+    }
+    if var_a == 0.0 || var_b == 0.0 {
+        return None;
+    }
+    Some(cov / (var_a * var_b).sqrt())
+}
+
+/// The name of the column holding `col`, and the decimals it's scaled by. That's `col` itself
+/// if the frame has it, or else its `PrecisionMode::Fixed` version, see `fixed_point_column`
+pub(crate) fn resolve_column(df: &DataFrame, col: &str) -> Option<(String, u32)> {
+    if df.column(col).is_ok() {
+        return Some((col.to_string(), 0));
+    }
+    #[cfg(feature = "fixed-point")]
+    for series in df.get_columns() {
+        let decimals = series
+            .name()
+            .strip_prefix(col)
+            .and_then(|suffix| suffix.strip_prefix("_e"))
+            .and_then(|decimals| decimals.parse().ok());
+        if let Some(decimals) = decimals {
+            if series.dtype().is_integer() {
+                return Some((series.name().to_string(), decimals));
+            }
+        }
+    }
+    None
+}
+
+/// The values of a column cast to f64, with fixed point prices converted back to decimals
+pub(crate) fn f64_values(df: &DataFrame, col: &str) -> Result<Vec<Option<f64>>, MoabError> {
+    let (name, decimals) = match resolve_column(df, col) {
+        Some(resolved) => resolved,
+        None => return Err(MoabError::DecodeError),
+    };
+    let scale = 10f64.powf(f64::from(decimals));
+    match df
+        .column(&name)
+        .and_then(|values| values.cast(&DataType::Float64))
+    {
+        Ok(values) => match values.f64() {
+            Ok(values) => Ok(values
+                .into_iter()
+                .map(|value| value.map(|value| value / scale))
+                .collect()),
+            Err(_) => Err(MoabError::DecodeError),
+        },
+        Err(_) => Err(MoabError::DecodeError),
+    }
+}
+
+/// Add a column repeating `symbol` on every row, so frames for different symbols can be
+/// concatenated without losing track of which rows are which.
+/// Does nothing if the frame already has a column named `col`.
+/// # Examples
+/// ```rust
+/// use moabdb::frame::{tag_symbol, SYMBOL_COLUMN};
+/// use polars::prelude::*;
+///
+/// let df = df!("close" => &[1.0, 2.0]).unwrap();
+/// let df = tag_symbol(df, "AAPL", SYMBOL_COLUMN).unwrap();
+/// assert_eq!(df.column("symbol").unwrap().utf8().unwrap().get(1), Some("AAPL"));
+/// ```
+pub fn tag_symbol(mut df: DataFrame, symbol: &str, col: &str) -> Result<DataFrame, MoabError> {
+    if df.column(col).is_ok() {
+        return Ok(df);
+    }
+    let tags = Series::new(col, vec![symbol; df.height()]);
+    match df.with_column(tags) {
+        Ok(_) => Ok(df),
+        Err(_) => Err(MoabError::DecodeError),
+    }
+}
+
+/// Rename the frame's columns to the canonical names in `CANONICAL_COLUMNS`, so frames of
+/// every datatype can be handled the same way.
+/// A column is left alone if the frame already has a column with its canonical name.
+/// # Examples
+/// ```rust
+/// use moabdb::frame::canonicalize_columns;
+/// use polars::prelude::*;
+///
+/// let df = df!("ts" => &[0i64], "vol" => &[10i64], "close" => &[1.0]).unwrap();
+/// let df = canonicalize_columns(df).unwrap();
+/// assert_eq!(df.get_column_names(), &["timestamp", "volume", "close"]);
+/// ```
+pub fn canonicalize_columns(mut df: DataFrame) -> Result<DataFrame, MoabError> {
+    for (from, to) in CANONICAL_COLUMNS {
+        if df.column(from).is_err() || df.column(to).is_ok() {
+            continue;
+        }
+        if df.rename(from, to).is_err() {
+            return Err(MoabError::DecodeError);
+        }
+    }
+    Ok(df)
+}
+
+/// Check that a frame has every one of `expected`, so a change to the server's parquet schema is
+/// caught at decode time instead of wherever the column is first used. A column may go by any name
+/// `canonicalize_columns` renames to the expected one, and extra columns are allowed.
+/// # Returns
+/// `MoabError::DecodeError` if a column is missing, since the frame's schema isn't one the caller
+/// supports
+///
+/// # Examples
+/// ```rust
+/// use moabdb::{errors::MoabError, frame::check_columns};
+/// use polars::prelude::*;
+///
+/// let df = df!("ts" => &[0i64], "close" => &[1.0], "extra" => &[2.0]).unwrap();
+/// assert!(check_columns(&df, &["timestamp", "close"]).is_ok());
+/// assert!(matches!(check_columns(&df, &["open"]), Err(MoabError::DecodeError)));
+/// ```
+pub fn check_columns(df: &DataFrame, expected: &[impl AsRef<str>]) -> Result<(), MoabError> {
+    let present = |col: &str| {
+        df.column(col).is_ok()
+            || CANONICAL_COLUMNS
+                .iter()
+                .any(|(from, to)| *to == col && df.column(from).is_ok())
+    };
+    match expected.iter().all(|col| present(col.as_ref())) {
+        true => Ok(()),
+        false => Err(MoabError::DecodeError),
+    }
+}
+
+/// Cast every float column of the frame to the float type of `precision`.
+/// Integer columns, such as volume and timestamps, are left alone.
+/// `PrecisionMode::Fixed` instead converts the float `PRICE_COLUMNS` to scaled integers, renamed
+/// to record their scale, see `fixed_point_column`; a price that's NaN, infinite or too large for
+/// an `i64` once scaled becomes null.
+/// # Examples
+/// ```rust
+/// use moabdb::frame::{with_precision, PrecisionMode};
+/// use polars::prelude::*;
+///
+/// let df = df!("volume" => &[10i64], "close" => &[1.5]).unwrap();
+/// let df = with_precision(df, PrecisionMode::F32).unwrap();
+/// assert_eq!(df.column("close").unwrap().dtype(), &DataType::Float32);
+/// assert_eq!(df.column("volume").unwrap().dtype(), &DataType::Int64);
+/// ```
+pub fn with_precision(mut df: DataFrame, precision: PrecisionMode) -> Result<DataFrame, MoabError> {
+    let (from, to) = match precision {
+        PrecisionMode::F64 => (DataType::Float32, DataType::Float64),
+        PrecisionMode::F32 => (DataType::Float64, DataType::Float32),
+        #[cfg(feature = "fixed-point")]
+        PrecisionMode::Fixed(decimals) => return to_fixed_point(df, decimals),
+    };
+    let names: Vec<String> = df
+        .get_columns()
+        .iter()
+        .filter(|col| col.dtype() == &from)
+        .map(|col| col.name().to_string())
+        .collect();
+    for name in names {
+        let cast = match df.column(&name).and_then(|col| col.cast(&to)) {
+            Ok(cast) => cast,
+            Err(_) => return Err(MoabError::DecodeError),
+        };
+        if df.replace(&name, cast).is_err() {
+            return Err(MoabError::DecodeError);
+        }
+    }
+    Ok(df)
+}
+
+/// The name `PrecisionMode::Fixed(decimals)` gives the fixed point version of a price column
+/// # Examples
+/// ```rust
+/// use moabdb::frame::{fixed_point_column, with_precision, PrecisionMode};
+/// use polars::prelude::*;
+///
+/// assert_eq!(fixed_point_column("close", 2), "close_e2");
+///
+/// let df = df!("close" => &[0.1 + 0.2, 123.45]).unwrap();
+/// let df = with_precision(df, PrecisionMode::Fixed(2)).unwrap();
+/// assert!(df.column("close").is_err());
+/// let cents: Vec<Option<i64>> = df.column("close_e2").unwrap().i64().unwrap().into_iter().collect();
+/// assert_eq!(cents, vec![Some(30), Some(12345)]);
+/// ```
+#[cfg(feature = "fixed-point")]
+pub fn fixed_point_column(name: &str, decimals: u32) -> String {
+    format!("{name}_e{decimals}")
+}
+
+/// Convert the float `PRICE_COLUMNS` to integers counting units of `10^-decimals`,
+/// renamed by `fixed_point_column`
+#[cfg(feature = "fixed-point")]
+fn to_fixed_point(mut df: DataFrame, decimals: u32) -> Result<DataFrame, MoabError> {
+    let scale = 10f64.powf(f64::from(decimals));
+    for name in PRICE_COLUMNS {
+        let prices = match df.column(name) {
+            Ok(prices) if prices.dtype().is_float() => prices,
+            _ => continue,
+        };
+        let prices = match prices.cast(&DataType::Float64) {
+            Ok(prices) => prices,
+            Err(_) => return Err(MoabError::DecodeError),
+        };
+        let units: Vec<Option<i64>> = match prices.f64() {
+            Ok(prices) => prices
+                .into_iter()
+                .map(|price| {
+                    let units = (price? * scale).round();
+                    // i64::MAX as f64 rounds up to 2^63, which is out of range
+                    (units.is_finite() && units >= i64::MIN as f64 && units < i64::MAX as f64)
+                        .then_some(units as i64)
+                })
+                .collect(),
+            Err(_) => return Err(MoabError::DecodeError),
+        };
+        if df.replace(name, Series::new(name, units)).is_err() {
+            return Err(MoabError::DecodeError);
+        }
+        if df
+            .rename(name, &fixed_point_column(name, decimals))
+            .is_err()
+        {
+            return Err(MoabError::DecodeError);
+        }
+    }
+    Ok(df)
+}
+
+/// The range of time a frame covers, from its first to its last bar
+/// # Arguments
+/// * `df` - The frame to check
+/// * `time_col` - The name of the timestamp column. It must be a datetime or epoch seconds
+///
+/// # Returns
+/// An inclusive window from the earliest to the latest timestamp, truncated to the second,
+/// or None if the frame has no timestamps
+///
+/// # Examples
+/// ```rust
+/// use moabdb::frame::time_range;
+/// use polars::prelude::*;
+///
+/// let df = df!("timestamp" => &[60i64, 0, 120]).unwrap();
+/// let range = time_range(&df, "timestamp").unwrap().unwrap();
+/// assert_eq!(<(i64, i64)>::from(range), (0, 120));
+/// ```
+pub fn time_range(df: &DataFrame, time_col: &str) -> Result<Option<Window>, MoabError> {
+    let time = match df.column(time_col) {
+        Ok(time) => time,
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    let units_per_second = match units_per_second(time.dtype()) {
+        Some(units) => units,
+        None => return Err(MoabError::DecodeError),
+    };
+    let time = match time.cast(&DataType::Int64) {
+        Ok(time) => time,
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    let (min, max) = match time.i64() {
+        Ok(time) => (time.min(), time.max()),
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    let to_datetime = |t: i64| -> Result<NaiveDateTime, MoabError> {
+        match DateTime::from_timestamp(t.div_euclid(units_per_second), 0) {
+            Some(dt) => Ok(dt.naive_utc()),
+            None => Err(MoabError::DecodeError),
+        }
+    };
+    match (min, max) {
+        (Some(min), Some(max)) => Ok(Some(Window {
+            start: to_datetime(min)?,
+            end: to_datetime(max)?,
+            bound: Bound::Inclusive,
+        })),
+        _ => Ok(None),
+    }
+}
+
+/// Check whether a frame of equity data covers the window it was requested for, so a delisted or
+/// halted symbol isn't mistaken for a complete one. The frame ends early when the bars expected
+/// after its last one, up to the end of `requested` or now if that's sooner, add up to more than
+/// three trading days, see `Window::estimate_rows`. The server doesn't say why data stops,
+/// so a delisting and a long halt look the same.
+/// # Examples
+/// ```rust
+/// use moabdb::{frame::coverage, window::Window};
+/// use polars::prelude::*;
+///
+/// // Daily bars through 2023-01-06, requested through the end of January
+/// let df = df!("timestamp" => &[1672876800i64, 1672963200]).unwrap();
+/// let requested = Window::try_from((1672531200, 1675123200)).unwrap();
+/// let covered = coverage(&df, "timestamp", requested, false).unwrap();
+/// assert!(covered.ends_early);
+/// assert_eq!(covered.last_available.unwrap().to_string(), "2023-01-06 00:00:00");
+/// // A window ending with the last bar is covered
+/// let through = Window::try_from((1672531200, 1672963200)).unwrap();
+/// assert!(!coverage(&df, "timestamp", through, false).unwrap().ends_early);
+/// ```
+pub fn coverage(
+    df: &DataFrame,
+    time_col: &str,
+    requested: Window,
+    intraday: bool,
+) -> Result<Coverage, MoabError> {
+    let last_available = time_range(df, time_col)?.map(|range| range.end);
+    let ends_early = match last_available {
+        Some(last) => {
+            let now = chrono::Utc::now().naive_utc();
+            let bound = if requested.end > now {
+                Bound::Inclusive
+            } else {
+                requested.bound
+            };
+            let missing = Window {
+                start: last + chrono::Duration::seconds(1),
+                end: requested.end.min(now),
+                bound,
+            };
+            let bars_per_day = if intraday {
+                let (open, close) = Market::Nyse.hours();
+                usize::try_from((close - open).num_minutes()).unwrap_or(0)
+            } else {
+                1
+            };
+            missing.estimate_rows(intraday, Market::Nyse) > ENDS_EARLY_TRADING_DAYS * bars_per_day
+        }
+        None => false,
+    };
+    Ok(Coverage {
+        last_available,
+        ends_early,
+    })
+}
+
+/// Reorder a frame's columns and rows as set by `spec`
+/// # Examples
+/// ```rust
+/// use moabdb::frame::{apply_output, OutputSpec, SortOrder};
+/// use polars::prelude::*;
+///
+/// let df = df!("close" => &[1.0, 2.0], "timestamp" => &[0i64, 60]).unwrap();
+/// let spec = OutputSpec::new().columns(&["timestamp"]).sort(SortOrder::Descending);
+/// let df = apply_output(df, &spec, "timestamp").unwrap();
+/// assert_eq!(df.get_column_names(), &["timestamp", "close"]);
+/// assert_eq!(df.column("timestamp").unwrap().i64().unwrap().get(0), Some(60));
+/// ```
+pub fn apply_output(
+    df: DataFrame,
+    spec: &OutputSpec,
+    time_col: &str,
+) -> Result<DataFrame, MoabError> {
+    let df = match &spec.columns {
+        Some(columns) => {
+            let mut order: Vec<String> = columns
+                .iter()
+                .filter(|col| df.column(col).is_ok())
+                .cloned()
+                .collect();
+            for name in df.get_column_names() {
+                if !order.iter().any(|col| col == name) {
+                    order.push(name.to_string());
+                }
+            }
+            match df.select(order) {
+                Ok(df) => df,
+                Err(_) => return Err(MoabError::DecodeError),
+            }
+        }
+        None => df,
+    };
+    match spec.sort {
+        Some(order) => match df.sort([time_col], order == SortOrder::Descending) {
+            Ok(df) => Ok(df),
+            Err(_) => Err(MoabError::DecodeError),
+        },
+        None => Ok(df),
+    }
+}
+
+/// Convert scaled integer prices, such as cents, to decimal prices by dividing each of the
+/// `PRICE_COLUMNS` by `scale`. Only integer columns are scaled, so a frame that already has
+/// decimal prices is returned unchanged.
+/// # Examples
+/// ```rust
+/// use moabdb::frame::scale_prices;
+/// use polars::prelude::*;
+///
+/// let df = df!("close" => &[12345i64], "volume" => &[100i64]).unwrap();
+/// let df = scale_prices(df, 100.0).unwrap();
+/// assert_eq!(df.column("close").unwrap().f64().unwrap().get(0), Some(123.45));
+/// assert_eq!(df.column("volume").unwrap().i64().unwrap().get(0), Some(100));
+/// ```
+pub fn scale_prices(mut df: DataFrame, scale: f64) -> Result<DataFrame, MoabError> {
+    for name in PRICE_COLUMNS {
+        let prices = match df.column(name) {
+            Ok(prices) if prices.dtype().is_integer() => prices,
+            _ => continue,
+        };
+        let scaled = match prices.cast(&DataType::Float64) {
+            Ok(prices) => prices / scale,
+            Err(_) => return Err(MoabError::DecodeError),
+        };
+        if df.replace(name, scaled).is_err() {
+            return Err(MoabError::DecodeError);
+        }
+    }
+    Ok(df)
+}
+
+/// Append a newer fetch to an existing frame, such as the result of `get_equity_since`, without
+/// duplicating the bars they overlap on. Rows of `existing` whose timestamp also appears in `new`
+/// are dropped, so the newer values win, and the rows of `new` are appended after the rest.
+/// Both frames must have the same columns.
+/// # Examples
+/// ```rust
+/// use moabdb::frame::append_dedup;
+/// use polars::prelude::*;
+///
+/// let existing = df!("timestamp" => &[0i64, 60], "close" => &[1.0, 2.0]).unwrap();
+/// let new = df!("timestamp" => &[60i64, 120], "close" => &[2.5, 3.0]).unwrap();
+/// let df = append_dedup(existing, new, "timestamp").unwrap();
+/// let close: Vec<Option<f64>> = df.column("close").unwrap().f64().unwrap().into_iter().collect();
+/// assert_eq!(close, vec![Some(1.0), Some(2.5), Some(3.0)]);
+/// ```
+pub fn append_dedup(
+    existing: DataFrame,
+    new: DataFrame,
+    time_col: &str,
+) -> Result<DataFrame, MoabError> {
+    let timestamps = |df: &DataFrame| -> Result<Vec<Option<i64>>, MoabError> {
+        let time = match df
+            .column(time_col)
+            .and_then(|time| time.cast(&DataType::Int64))
+        {
+            Ok(time) => time,
+            Err(_) => return Err(MoabError::DecodeError),
+        };
+        match time.i64() {
+            Ok(time) => Ok(time.into_iter().collect()),
+            Err(_) => Err(MoabError::DecodeError),
+        }
+    };
+    let replaced: HashSet<i64> = timestamps(&new)?.into_iter().flatten().collect();
+    let keep: BooleanChunked = timestamps(&existing)?
+        .into_iter()
+        .map(|t| !matches!(t, Some(t) if replaced.contains(&t)))
+        .collect();
+    let mut kept = match existing.filter(&keep) {
+        Ok(kept) => kept,
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    match kept.vstack_mut(&new) {
+        Ok(_) => Ok(kept),
+        Err(_) => Err(MoabError::DecodeError),
+    }
+}
+
+/// The bars whose values changed between two fetches of the same symbol and window, such as
+/// history the vendor restated. Rows are matched on `key`, usually the timestamp, which should be
+/// unique in each frame. A bar differs when any column both frames have holds a different value
+/// or type, or is null in only one of them; a NaN in both counts as unchanged. Bars present in
+/// only one fetch aren't included.
+/// # Returns
+/// The changed bars sorted by `key`, with `new`'s columns followed by `old`'s columns suffixed
+/// with `_old`, so each changed value can be read next to what it was
+///
+/// # Examples
+/// ```rust
+/// use moabdb::frame::diff_frames;
+/// use polars::prelude::*;
+///
+/// let old = df!("timestamp" => &[0i64, 60, 120], "close" => &[Some(1.0), Some(2.0), None]).unwrap();
+/// let new = df!("timestamp" => &[0i64, 60, 120], "close" => &[Some(1.0), Some(2.5), None]).unwrap();
+/// let changed = diff_frames(&old, &new, "timestamp").unwrap();
+/// assert_eq!(changed.get_column_names(), &["timestamp", "close", "close_old"]);
+/// assert_eq!(changed.column("timestamp").unwrap().i64().unwrap().get(0), Some(60));
+/// assert_eq!(changed.column("close_old").unwrap().f64().unwrap().get(0), Some(2.0));
+/// assert_eq!(changed.height(), 1);
+///
+/// let old = df!("timestamp" => &[0i64, 60], "close" => &[f64::NAN, f64::NAN]).unwrap();
+/// let new = df!("timestamp" => &[0i64, 60], "close" => &[f64::NAN, 1.0]).unwrap();
+/// let changed = diff_frames(&old, &new, "timestamp").unwrap();
+/// assert_eq!(changed.column("timestamp").unwrap().i64().unwrap().get(0), Some(60));
+/// assert_eq!(changed.height(), 1);
+/// ```
+pub fn diff_frames(old: &DataFrame, new: &DataFrame, key: &str) -> Result<DataFrame, MoabError> {
+    use polars::prelude::JoinType;
+
+    let joined = match new.join(old, [key], [key], JoinType::Inner, Some("_old".to_string())) {
+        Ok(joined) => joined,
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    let mut changed = vec![false; joined.height()];
+    for name in new.get_column_names() {
+        if name == key || old.column(name).is_err() {
+            continue;
+        }
+        let (current, previous) = match (joined.column(name), joined.column(&format!("{name}_old")))
+        {
+            (Ok(current), Ok(previous)) => (current.rechunk(), previous.rechunk()),
+            _ => return Err(MoabError::DecodeError),
+        };
+        for (changed, (current, previous)) in
+            changed.iter_mut().zip(current.iter().zip(previous.iter()))
+        {
+            *changed |= !same_value(&current, &previous);
+        }
+    }
+    let changed: BooleanChunked = changed.into_iter().collect();
+    match joined
+        .filter(&changed)
+        .and_then(|changed| changed.sort([key], false))
+    {
+        Ok(changed) => Ok(changed),
+        Err(_) => Err(MoabError::DecodeError),
+    }
+}
+
+/// Whether two values are the same, counting two NaNs of the same type as the same
+fn same_value(a: &AnyValue, b: &AnyValue) -> bool {
+    match (a, b) {
+        (AnyValue::Float32(a), AnyValue::Float32(b)) if a.is_nan() && b.is_nan() => true,
+        (AnyValue::Float64(a), AnyValue::Float64(b)) if a.is_nan() && b.is_nan() => true,
+        _ => a == b,
+    }
+}
+
+/// Append `new` to the end of `target` in place. The rows are appended as new chunks without
+/// copying `target`, so call `DataFrame::rechunk` once done appending for faster reads.
+/// A `target` without columns is replaced by `new`.
+/// # Returns
+/// `MoabError::DecodeError` if the frames' column names, order or types differ, leaving `target` as it was
+///
+/// # Examples
+/// ```rust
+/// use moabdb::frame::extend;
+/// use polars::prelude::*;
+///
+/// let mut target = DataFrame::default();
+/// extend(&mut target, &df!("timestamp" => &[0i64], "close" => &[1.0]).unwrap()).unwrap();
+/// extend(&mut target, &df!("timestamp" => &[60i64], "close" => &[2.0]).unwrap()).unwrap();
+/// assert_eq!(target.height(), 2);
+/// assert!(extend(&mut target, &df!("close" => &[3.0]).unwrap()).is_err());
+/// ```
+pub fn extend(target: &mut DataFrame, new: &DataFrame) -> Result<(), MoabError> {
+    if target.width() == 0 {
+        *target = new.clone();
+        return Ok(());
+    }
+    if target.schema() != new.schema() {
+        return Err(MoabError::DecodeError);
+    }
+    match target.vstack_mut(new) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(MoabError::DecodeError),
+    }
+}
+
+/// Keep only the bars of intraday data that start in `session` of `market`, see `Session::contains`.
+/// The time column can be Unix timestamps in seconds or a `Datetime` column in UTC.
+/// Rows with a null time are dropped unless `session` is `Session::All`, which keeps the frame as it is.
+/// # Examples
+/// ```rust
+/// use moabdb::{frame::filter_session, window::{Market, Session}};
+/// use polars::prelude::*;
+///
+/// // 2023-07-03 at 9:00, 9:30 and 16:00 in New York
+/// let df = df!("timestamp" => &[1688389200i64, 1688391000, 1688414400]).unwrap();
+/// let df = filter_session(df, "timestamp", Session::Regular, Market::Nyse).unwrap();
+/// assert_eq!(df.column("timestamp").unwrap().i64().unwrap().get(0), Some(1688391000));
+/// assert_eq!(df.height(), 1);
+/// ```
+pub fn filter_session(
+    df: DataFrame,
+    time_col: &str,
+    session: Session,
+    market: Market,
+) -> Result<DataFrame, MoabError> {
+    if session == Session::All {
+        return Ok(df);
+    }
+    let time = match df.column(time_col) {
+        Ok(time) => time,
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    let units_per_second = match units_per_second(time.dtype()) {
+        Some(units) => units,
+        None => return Err(MoabError::DecodeError),
+    };
+    let time = match time.cast(&DataType::Int64) {
+        Ok(time) => time,
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    let keep: BooleanChunked = match time.i64() {
+        Ok(time) => time
+            .into_iter()
+            .map(|t| {
+                t.and_then(|t| DateTime::from_timestamp(t.div_euclid(units_per_second), 0))
+                    .is_some_and(|t| session.contains(t.naive_utc(), market))
+            })
+            .collect(),
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    match df.filter(&keep) {
+        Ok(df) => Ok(df),
+        Err(_) => Err(MoabError::DecodeError),
+    }
+}
+
+/// Make a frame's time column a sorted key, for operations such as `groupby_dynamic` and `join_asof`.
+/// An integer column of Unix timestamps in seconds is converted to a millisecond `Datetime` column,
+/// and a `Datetime` column is kept as it is. The frame is then sorted by the column, ascending,
+/// and the column is flagged as sorted.
+/// # Examples
+/// ```rust
+/// use moabdb::frame::to_time_index;
+/// use polars::prelude::*;
+///
+/// let df = df!("timestamp" => &[60i64, 0], "close" => &[2.0, 1.0]).unwrap();
+/// let df = to_time_index(df, "timestamp").unwrap();
+/// let time = df.column("timestamp").unwrap();
+/// assert_eq!(time.dtype(), &DataType::Datetime(TimeUnit::Milliseconds, None));
+/// assert_eq!(df.column("close").unwrap().f64().unwrap().get(0), Some(1.0));
+/// ```
+pub fn to_time_index(mut df: DataFrame, time_col: &str) -> Result<DataFrame, MoabError> {
+    let time = match df.column(time_col) {
+        Ok(time) => time,
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    let time = match time.dtype() {
+        DataType::Datetime(_, _) => None,
+        dtype if dtype.is_integer() => match time.cast(&DataType::Int64) {
+            Ok(seconds) => Some(&seconds * 1000),
+            Err(_) => return Err(MoabError::DecodeError),
+        },
+        _ => return Err(MoabError::DecodeError),
+    };
+    if let Some(millis) = time {
+        let datetime = match millis.cast(&DataType::Datetime(TimeUnit::Milliseconds, None)) {
+            Ok(datetime) => datetime,
+            Err(_) => return Err(MoabError::DecodeError),
+        };
+        if df.replace(time_col, datetime).is_err() {
+            return Err(MoabError::DecodeError);
+        }
+    }
+    let mut df = match df.sort([time_col], false) {
+        Ok(df) => df,
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    let mut time = match df.column(time_col) {
+        Ok(time) => time.clone(),
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    time.set_sorted(IsSorted::Ascending);
+    match df.replace(time_col, time) {
+        Ok(_) => Ok(df),
+        Err(_) => Err(MoabError::DecodeError),
+    }
+}
+
+/// Compute a single value over every bar of a frame, ignoring nulls.
+/// The columns are looked up by their canonical names, see `canonicalize_columns`.
+/// # Returns
+/// The value, `MoabError::NotFound` if the frame has no bars to compute it from, or
+/// `MoabError::DecodeError` if a column it needs is missing or isn't numeric
+///
+/// # Examples
+/// ```rust
+/// use moabdb::frame::{aggregate, Aggregation};
+/// use polars::prelude::*;
+///
+/// let df = df!(
+///     "high" => &[3.0, 6.0],
+///     "low" => &[1.0, 4.0],
+///     "close" => &[2.0, 5.0],
+///     "volume" => &[100i64, 300],
+/// ).unwrap();
+/// assert_eq!(aggregate(&df, &Aggregation::MeanVolume).unwrap(), 200.0);
+/// assert_eq!(aggregate(&df, &Aggregation::Vwap).unwrap(), 4.25);
+/// ```
+pub fn aggregate(df: &DataFrame, agg: &Aggregation) -> Result<f64, MoabError> {
+    let df = canonicalize_columns(df.clone())?;
+    let values = |col: &str| -> Result<Vec<f64>, MoabError> {
+        Ok(f64_values(&df, col)?.into_iter().flatten().collect())
+    };
+    let value = match agg {
+        Aggregation::Vwap => {
+            let high = f64_values(&df, "high")?;
+            let low = f64_values(&df, "low")?;
+            let close = f64_values(&df, "close")?;
+            let volume = f64_values(&df, "volume")?;
+            let (mut traded, mut total) = (0.0, 0.0);
+            for i in 0..df.height() {
+                if let (Some(h), Some(l), Some(c), Some(v)) = (high[i], low[i], close[i], volume[i])
+                {
+                    traded += (h + l + c) / 3.0 * v;
+                    total += v;
+                }
+            }
+            (total != 0.0).then(|| traded / total)
+        }
+        Aggregation::MeanVolume => mean(&values("volume")?),
+        Aggregation::TotalVolume => {
+            let volume = values("volume")?;
+            (!volume.is_empty()).then(|| volume.iter().sum())
+        }
+        Aggregation::High => values("high")?.into_iter().reduce(f64::max),
+        Aggregation::Low => values("low")?.into_iter().reduce(f64::min),
+        Aggregation::Mean(col) => mean(&values(col)?),
+    };
+    match value {
+        Some(value) => Ok(value),
+        None => Err(MoabError::NotFound),
+    }
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+/// Check that a frame's time column is strictly increasing, with no nulls or duplicates,
+/// to catch glitches in fetched data before relying on it.
+/// # Returns
+/// `MoabError::DecodeError` if a timestamp is null or isn't after the previous row's, or if
+/// there's no integer or datetime column named `time_col`. Use `first_invalid_timestamp` to
+/// find the offending row.
+///
+/// # Examples
+/// ```rust
+/// use moabdb::{errors::MoabError, frame::validate_timeseries};
+/// use polars::prelude::*;
+///
+/// let df = df!("timestamp" => &[0i64, 60, 120]).unwrap();
+/// assert!(validate_timeseries(&df, "timestamp").is_ok());
+/// let df = df!("timestamp" => &[0i64, 60, 60, 30]).unwrap();
+/// assert!(matches!(validate_timeseries(&df, "timestamp"), Err(MoabError::DecodeError)));
+/// ```
+pub fn validate_timeseries(df: &DataFrame, time_col: &str) -> Result<(), MoabError> {
+    match first_invalid_timestamp(df, time_col)? {
+        Some(_) => Err(MoabError::DecodeError),
+        None => Ok(()),
+    }
+}
+
+/// Find the first row that breaks a strictly increasing time column, see `validate_timeseries`
+/// # Returns
+/// The index of the first row whose timestamp is null or isn't after the previous row's, or None
+/// if the column is valid. `MoabError::DecodeError` if there's no integer or datetime column
+/// named `time_col`
+///
+/// # Examples
+/// ```rust
+/// use moabdb::frame::first_invalid_timestamp;
+/// use polars::prelude::*;
+///
+/// let df = df!("timestamp" => &[0i64, 60, 60, 30]).unwrap();
+/// assert_eq!(first_invalid_timestamp(&df, "timestamp").unwrap(), Some(2));
+/// ```
+pub fn first_invalid_timestamp(df: &DataFrame, time_col: &str) -> Result<Option<usize>, MoabError> {
+    let time = match df.column(time_col) {
+        Ok(time) if units_per_second(time.dtype()).is_some() => time,
+        _ => return Err(MoabError::DecodeError),
+    };
+    let time = match time.cast(&DataType::Int64) {
+        Ok(time) => time,
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    let time = match time.i64() {
+        Ok(time) => time,
+        Err(_) => return Err(MoabError::DecodeError),
+    };
+    let mut previous = None;
+    for (row, t) in time.into_iter().enumerate() {
+        match (t, previous) {
+            (None, _) => return Ok(Some(row)),
+            (Some(t), Some(previous)) if t <= previous => return Ok(Some(row)),
+            (t, _) => previous = t,
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,94 @@
+// Jackson Coxson
+
+use std::time::Duration;
+
+/// A hook called around every request a `Client` sends, for logging, auditing or adding headers.
+/// Register one on a `Client` with `Client::with_interceptor`. Both methods do nothing by default.
+///
+/// # Examples
+/// ```
+/// use moabdb::{client::Client, interceptor::{Interceptor, OutgoingRequest, ResponseEvent}};
+///
+/// struct Audit;
+///
+/// impl Interceptor for Audit {
+///     fn before_request(&self, request: &mut OutgoingRequest) {
+///         request.set_header("x-request-source", "nightly-backfill");
+///     }
+///
+///     fn after_response(&self, response: &ResponseEvent) {
+///         println!("{} {:?} {} bytes", response.symbol, response.code, response.bytes);
+///     }
+/// }
+///
+/// let client = Client::new(None).with_interceptor(Audit);
+/// ```
+pub trait Interceptor: Send + Sync {
+    /// Called before a request is sent
+    fn before_request(&self, _request: &mut OutgoingRequest) {}
+    /// Called once a response is received, or the request fails, before the data is decoded
+    fn after_response(&self, _response: &ResponseEvent) {}
+}
+
+/// A request about to be sent. The request itself can't be changed, but headers can be added.
+#[derive(Debug, Clone)]
+pub struct OutgoingRequest {
+    pub(crate) symbol: String,
+    pub(crate) datatype: String,
+    pub(crate) start: i64,
+    pub(crate) end: i64,
+    pub(crate) username: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) tag: Option<String>,
+}
+
+impl OutgoingRequest {
+    /// The symbol being requested
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+    /// The datatype being requested, such as `daily_stocks`
+    pub fn datatype(&self) -> &str {
+        &self.datatype
+    }
+    /// The start and end Unix timestamps sent to the server, both inclusive
+    pub fn timestamps(&self) -> (i64, i64) {
+        (self.start, self.end)
+    }
+    /// The username the request is authenticated as, empty if it's unauthenticated
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+    /// The tag the request is made under, see `tag::with_tag`. It isn't sent to the server
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+    /// The headers that will be sent with the request, besides the request itself
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+    /// Send a header with the request, replacing any earlier value for `name`
+    pub fn set_header(&mut self, name: impl AsRef<str>, value: impl AsRef<str>) {
+        let name = name.as_ref();
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.headers
+            .push((name.to_string(), value.as_ref().to_string()));
+    }
+}
+
+/// A response received, or a request that failed to get one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseEvent {
+    /// The symbol that was requested
+    pub symbol: String,
+    /// The datatype that was requested, such as `daily_stocks`
+    pub datatype: String,
+    /// The status code returned by the server, None if no response could be read
+    pub code: Option<u32>,
+    /// The size of the response body as received, in base64
+    pub bytes: usize,
+    /// How long the request took, not including decoding the data
+    pub duration: Duration,
+    /// The tag the request was made under, see `tag::with_tag`
+    pub tag: Option<String>,
+}
@@ -1,12 +1,28 @@
-#[doc = include_str!("../README.md")]
+#![doc = include_str!("../README.md")]
 
 const API_URL: &str = "https://api.moabdb.com/request/v1/";
 
+use std::collections::HashMap;
+
 use polars::prelude::DataFrame;
 
+mod circuit;
+pub mod client;
+mod coalesce;
 pub mod credentials;
+#[cfg(feature = "dangerous-testing")]
+mod danger;
 pub mod errors;
+pub mod frame;
+pub mod interceptor;
+pub mod metrics;
+#[cfg(feature = "otel")]
+mod otel;
 mod protocol;
+#[cfg(feature = "lazy")]
+mod scan;
+pub mod tag;
+mod throttle;
 pub mod window;
 
 /// Get the equity data for a given ticker
@@ -38,70 +54,790 @@ pub fn get_equity(
     intraday: bool,
     credentials: Option<credentials::Credentials>,
 ) -> Result<DataFrame, errors::MoabError> {
-    use polars::prelude::{ParquetReader, SerReader};
-
-    let datatype = if intraday {
-        "intraday_stocks"
-    } else {
-        "daily_stocks"
-    };
-    let req = protocol::Request {
-        symbol: ticker.as_ref().to_string(),
-        start: window.start.timestamp() as u32,
-        end: window.end.timestamp() as u32,
-        datatype: datatype.to_string(),
-        username: match credentials {
-            Some(ref creds) => creds.username.to_string(),
-            None => "".to_string(),
-        },
-        token: match credentials {
-            Some(ref creds) => creds.token.to_string(),
-            None => "".to_string(),
-        },
-    };
-    let req = req.b64();
-
-    let mut resp = match ureq::get(API_URL).set("x-req", &req).call() {
-        Ok(resp) => resp.into_reader(),
-        Err(_) => return Err(errors::MoabError::TransportError),
-    };
-
-    // Read the resp to end
-    let mut buffer = Vec::new();
-    match resp.read_to_end(&mut buffer) {
-        Ok(_) => (),
-        Err(_) => return Err(errors::MoabError::TransportError),
+    client::Client::new(credentials).get_equity(ticker, window, intraday)
+}
+
+/// Get the data for a given symbol and datatype.
+/// This is the request every other function is built on, for datatypes the crate doesn't wrap yet.
+/// # Arguments
+/// * `symbol` - The symbol to get data for
+/// * `window` - The window of time to get data for. Build a window with the `WindowBuilder`
+/// * `datatype` - The server's name for the dataset, such as `daily_stocks` or `intraday_stocks`
+/// * `credentials` - The credentials to use to authenticate the request. If None, the request will be unauthenticated
+///
+/// # Returns
+/// A `DataFrame` containing the data
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{get_data, window::WindowBuilder, window::WindowLength};
+///
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Months(3))
+///     .build()
+///     .unwrap();
+///
+/// let df = get_data("AAPL", window, "daily_stocks", None).unwrap();
+/// println!("{:?}", df);
+/// ```
+///
+pub fn get_data(
+    symbol: impl AsRef<str>,
+    window: window::Window,
+    datatype: impl AsRef<str>,
+    credentials: Option<credentials::Credentials>,
+) -> Result<DataFrame, errors::MoabError> {
+    client::Client::new(credentials).get_data(symbol, window, datatype)
+}
+
+/// Get the most recent bars for a given ticker, however many calendar days they span
+/// # Arguments
+/// * `ticker` - The ticker symbol of the equity
+/// * `n` - The number of bars to get
+/// * `intraday` - Whether to get intraday bars or daily bars
+/// * `credentials` - The credentials to use to authenticate the request. If None, the request will be unauthenticated
+///
+/// # Returns
+/// A `DataFrame` with the last `n` bars, sorted by timestamp. The protocol has no limit on the
+/// number of rows, so this requests a window generous enough to hold `n` bars (about two calendar
+/// days per daily bar, or two days per 390 intraday bars) and keeps the last `n` rows.
+/// Fewer than `n` rows are returned if the ticker doesn't have that many bars in that window,
+/// such as a recent listing, so check the frame's height if you need exactly `n`.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::get_equity_last_n;
+///
+/// let df = get_equity_last_n("AAPL", 100, false, None).unwrap();
+/// println!("{:?}", df);
+/// ```
+///
+pub fn get_equity_last_n(
+    ticker: impl AsRef<str>,
+    n: usize,
+    intraday: bool,
+    credentials: Option<credentials::Credentials>,
+) -> Result<DataFrame, errors::MoabError> {
+    client::Client::new(credentials).get_equity_last_n(ticker, n, intraday)
+}
+
+/// Get the close of the most recent daily bar for a given ticker, without handling a frame
+/// # Arguments
+/// * `ticker` - The ticker symbol of the equity
+/// * `credentials` - The credentials to use to authenticate the request. If None, the request will be unauthenticated
+///
+/// # Returns
+/// The last close, see `get_equity_last_n`, or `MoabError::NotFound` if the ticker has no recent
+/// daily bar or its close is missing. A NaN close is never returned.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::get_last_close;
+///
+/// let close = get_last_close("AAPL", None).unwrap();
+/// println!("{}", close);
+/// ```
+///
+pub fn get_last_close(
+    ticker: impl AsRef<str>,
+    credentials: Option<credentials::Credentials>,
+) -> Result<f64, errors::MoabError> {
+    client::Client::new(credentials).get_last_close(ticker)
+}
+
+/// Get the equity data for a given ticker added since a previous fetch, for incremental syncs
+/// # Arguments
+/// * `ticker` - The ticker symbol of the equity
+/// * `last` - The timestamp of the last bar already stored, in UTC
+/// * `intraday` - Whether to get intraday data or daily data
+/// * `drop_boundary` - Whether to leave out the bar at exactly `last`, so the stored last bar isn't fetched again
+/// * `credentials` - The credentials to use to authenticate the request. If None, the request will be unauthenticated
+///
+/// # Returns
+/// A `DataFrame` containing the bars from `last` until now, which is empty if there are no new bars.
+/// Fails with `MoabError::RequestError` if `last` is in the future.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::get_equity_since;
+///
+/// let last = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap().naive_utc();
+/// let new_rows = get_equity_since("AAPL", last, false, true, None).unwrap();
+/// println!("{:?}", new_rows);
+/// ```
+///
+pub fn get_equity_since(
+    ticker: impl AsRef<str>,
+    last: chrono::NaiveDateTime,
+    intraday: bool,
+    drop_boundary: bool,
+    credentials: Option<credentials::Credentials>,
+) -> Result<DataFrame, errors::MoabError> {
+    client::Client::new(credentials).get_equity_since(ticker, last, intraday, drop_boundary)
+}
+
+/// Get all the equity data the server has for a given ticker, without knowing when it starts
+/// # Arguments
+/// * `ticker` - The ticker symbol of the equity
+/// * `intraday` - Whether to get intraday data or daily data
+/// * `credentials` - The credentials to use to authenticate the request. If None, the request will be unauthenticated
+///
+/// # Returns
+/// A `DataFrame` containing the equity data, along with the range it actually covers, from the
+/// first to the last bar. The range is None if the server has no data for the ticker.
+/// The protocol can't ask for a symbol's available range, so this requests a single window from
+/// 1900 until now and lets the server return everything it has. The whole history is held in
+/// memory, which can be large for intraday data.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::get_equity_full_history;
+///
+/// let (df, range) = get_equity_full_history("AAPL", false, None).unwrap();
+/// println!("{} bars covering {:?}", df.height(), range);
+/// ```
+///
+pub fn get_equity_full_history(
+    ticker: impl AsRef<str>,
+    intraday: bool,
+    credentials: Option<credentials::Credentials>,
+) -> Result<(DataFrame, Option<window::Window>), errors::MoabError> {
+    client::Client::new(credentials).get_equity_full_history(ticker, intraday)
+}
+
+/// Get the first and last timestamps the server has data for, to plan a backfill
+/// # Arguments
+/// * `symbol` - The symbol to check
+/// * `datatype` - The server's name for the dataset, such as `daily_stocks` or `intraday_stocks`
+/// * `credentials` - The credentials to use to authenticate the request. If None, the request will be unauthenticated
+///
+/// # Returns
+/// An inclusive `Window` from the first to the last bar, or `MoabError::NotFound` if there is no data.
+/// The protocol has no metadata query and the start of the data isn't known ahead of time, so this
+/// downloads the full history like `get_equity_full_history` and keeps only its range. It saves
+/// memory downstream, not bandwidth.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::get_available_range;
+///
+/// let range = get_available_range("AAPL", "daily_stocks", None).unwrap();
+/// println!("AAPL daily data covers {}", range);
+/// ```
+///
+pub fn get_available_range(
+    symbol: impl AsRef<str>,
+    datatype: impl AsRef<str>,
+    credentials: Option<credentials::Credentials>,
+) -> Result<window::Window, errors::MoabError> {
+    client::Client::new(credentials).get_available_range(symbol, datatype)
+}
+
+/// Get the equity data for several tickers over the same window
+/// # Arguments
+/// * `tickers` - The ticker symbols of the equities
+/// * `window` - The window of time to get data for. Build a window with the `WindowBuilder`
+/// * `intraday` - Whether to get intraday data or daily data
+/// * `credentials` - The credentials to use to authenticate the requests. If None, the requests will be unauthenticated
+///
+/// # Returns
+/// A map of each ticker to the result of its request. The requests are made concurrently and share one connection pool.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{get_equities, window::WindowBuilder, window::WindowLength};
+///
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Months(3))
+///     .build()
+///     .unwrap();
+///
+/// let dfs = get_equities(&["AAPL", "MSFT"], window, false, None);
+/// println!("{:?}", dfs["AAPL"]);
+/// ```
+///
+pub fn get_equities(
+    tickers: &[impl AsRef<str>],
+    window: window::Window,
+    intraday: bool,
+    credentials: Option<credentials::Credentials>,
+) -> HashMap<String, Result<DataFrame, errors::MoabError>> {
+    client::Client::new(credentials).get_equities(tickers, window, intraday)
+}
+
+/// Get the equity data for several tickers over the same window, in the same order as the tickers
+/// however the requests complete, for reproducible output
+/// # Arguments
+/// * `tickers` - The ticker symbols of the equities
+/// * `window` - The window of time to get data for. Build a window with the `WindowBuilder`
+/// * `intraday` - Whether to get intraday data or daily data
+/// * `credentials` - The credentials to use to authenticate the requests. If None, the requests will be unauthenticated
+///
+/// # Returns
+/// Each ticker with its data, or the error its request failed with, in the order of `tickers`.
+/// A ticker listed more than once appears once for each time it's listed.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{get_equities_ordered, window::WindowBuilder, window::WindowLength};
+///
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Years(1))
+///     .build()
+///     .unwrap();
+///
+/// for (ticker, df) in get_equities_ordered(&["AAPL", "MSFT", "GOOG"], window, false, None) {
+///     println!("{}: {:?}", ticker, df.map(|df| df.height()));
+/// }
+/// ```
+///
+pub fn get_equities_ordered(
+    tickers: &[impl AsRef<str>],
+    window: window::Window,
+    intraday: bool,
+    credentials: Option<credentials::Credentials>,
+) -> Vec<(String, Result<DataFrame, errors::MoabError>)> {
+    client::Client::new(credentials).get_equities_ordered(tickers, window, intraday)
+}
+
+/// Fetch the equity data for several tickers over the same window concurrently, sending each
+/// frame into a channel as soon as its request completes, for producer/consumer pipelines
+/// # Arguments
+/// * `tickers` - The ticker symbols of the equities
+/// * `window` - The window of time to get data for. Build a window with the `WindowBuilder`
+/// * `intraday` - Whether to get intraday data or daily data
+/// * `credentials` - The credentials to use to authenticate the requests. If None, the requests will be unauthenticated
+/// * `frames` - Where each ticker's data is sent
+/// * `errors` - Where each ticker whose request failed is sent, with its error
+///
+/// # Returns
+/// Once every ticker has been sent to one of the channels, in the order the requests complete.
+/// Both senders are dropped then, so receivers stop once every other sender is dropped too.
+/// Results for a receiver that hung up are discarded.
+///
+/// # Examples
+/// ```no_run
+/// use std::sync::mpsc;
+/// use moabdb::{errors::MoabError, get_equities_to_channel, window::WindowBuilder, window::WindowLength};
+/// use polars::prelude::DataFrame;
+///
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Years(1))
+///     .build()
+///     .unwrap();
+///
+/// let (frames, received) = mpsc::channel::<(String, DataFrame)>();
+/// let (errors, failed) = mpsc::channel::<(String, MoabError)>();
+/// let consumer = std::thread::spawn(move || {
+///     for (ticker, df) in received {
+///         println!("{}: {} rows", ticker, df.height());
+///     }
+/// });
+/// get_equities_to_channel(&["AAPL", "MSFT"], window, false, None, frames, errors);
+/// consumer.join().unwrap();
+/// for (ticker, e) in failed {
+///     println!("{}: {:?}", ticker, e);
+/// }
+/// ```
+///
+pub fn get_equities_to_channel(
+    tickers: &[impl AsRef<str>],
+    window: window::Window,
+    intraday: bool,
+    credentials: Option<credentials::Credentials>,
+    frames: std::sync::mpsc::Sender<(String, DataFrame)>,
+    errors: std::sync::mpsc::Sender<(String, errors::MoabError)>,
+) {
+    client::Client::new(credentials)
+        .get_equities_to_channel(tickers, window, intraday, frames, errors)
+}
+
+/// Get the equity data for a given ticker over several windows, such as the days around each
+/// earnings date, fetched concurrently over the same connection pool
+/// # Arguments
+/// * `ticker` - The ticker symbol of the equity
+/// * `windows` - The windows of time to get data for. Build windows with the `WindowBuilder`
+/// * `intraday` - Whether to get intraday data or daily data
+/// * `credentials` - The credentials to use to authenticate the requests. If None, the requests will be unauthenticated
+///
+/// # Returns
+/// The data for each window, or the error its request failed with, in the order of `windows`
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{get_equity_windows, window::Window};
+///
+/// let earnings = [1690848000, 1698796800];
+/// let windows: Vec<Window> = earnings
+///     .iter()
+///     .map(|t| Window::try_from((t - 86400 * 5, t + 86400 * 5)).unwrap())
+///     .collect();
+///
+/// for df in get_equity_windows("AAPL", &windows, true, None) {
+///     println!("{:?}", df);
+/// }
+/// ```
+///
+pub fn get_equity_windows(
+    ticker: impl AsRef<str>,
+    windows: &[window::Window],
+    intraday: bool,
+    credentials: Option<credentials::Credentials>,
+) -> Vec<Result<DataFrame, errors::MoabError>> {
+    client::Client::new(credentials).get_equity_windows(ticker, windows, intraday)
+}
+
+/// Get the equity data for a given ticker along with whether it stops short of the end of the
+/// window, so delisted or halted symbols can be handled instead of misaligning a panel
+/// # Arguments
+/// * `ticker` - The ticker symbol of the equity
+/// * `window` - The window of time to get data for. Build a window with the `WindowBuilder`
+/// * `intraday` - Whether to get intraday data or daily data
+/// * `credentials` - The credentials to use to authenticate the request. If None, the request will be unauthenticated
+///
+/// # Returns
+/// The data and its `Coverage`: the time of the last bar, and whether the bars end more than a
+/// few trading days before the end of the window, see `frame::coverage`
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{get_equity_with_coverage, window::WindowBuilder, window::WindowLength};
+///
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Years(1))
+///     .build()
+///     .unwrap();
+///
+/// let (df, coverage) = get_equity_with_coverage("SIVB", window, false, None).unwrap();
+/// if coverage.ends_early {
+///     println!("No data after {:?}", coverage.last_available);
+/// }
+/// ```
+///
+pub fn get_equity_with_coverage(
+    ticker: impl AsRef<str>,
+    window: window::Window,
+    intraday: bool,
+    credentials: Option<credentials::Credentials>,
+) -> Result<(DataFrame, frame::Coverage), errors::MoabError> {
+    client::Client::new(credentials).get_equity_with_coverage(ticker, window, intraday)
+}
+
+/// Get one column of equity data for several tickers as a single frame with a column per ticker
+/// # Arguments
+/// * `tickers` - The ticker symbols of the equities
+/// * `window` - The window of time to get data for. Build a window with the `WindowBuilder`
+/// * `intraday` - Whether to get intraday data or daily data
+/// * `credentials` - The credentials to use to authenticate the requests. If None, the requests will be unauthenticated
+/// * `value_col` - The column to take from each ticker's data, such as the close
+///
+/// # Returns
+/// A frame with the timestamp column and one column per ticker, outer joined on timestamp and
+/// sorted, along with the tickers that were left out because their request failed or had no `value_col`.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{get_panel, window::WindowBuilder, window::WindowLength};
+///
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Years(1))
+///     .build()
+///     .unwrap();
+///
+/// let (panel, missing) = get_panel(&["AAPL", "MSFT", "GOOG"], window, false, None, "close").unwrap();
+/// println!("{:?}, missing {:?}", panel, missing);
+/// ```
+///
+pub fn get_panel(
+    tickers: &[impl AsRef<str>],
+    window: window::Window,
+    intraday: bool,
+    credentials: Option<credentials::Credentials>,
+    value_col: &str,
+) -> Result<(DataFrame, Vec<String>), errors::MoabError> {
+    client::Client::new(credentials).get_panel(tickers, window, intraday, value_col)
+}
+
+/// Get the equity data for several tickers as tables of a polars SQL context, to query with SQL.
+/// Requires the `sql` feature.
+/// # Arguments
+/// * `tickers` - The ticker symbols of the equities
+/// * `window` - The window of time to get data for. Build a window with the `WindowBuilder`
+/// * `intraday` - Whether to get intraday data or daily data
+/// * `credentials` - The credentials to use to authenticate the requests. If None, the requests will be unauthenticated
+///
+/// # Returns
+/// A `SQLContext` with one table per ticker, named by the ticker, along with the tickers that were
+/// left out because their request failed. Polars allows only one SQL context per thread at a time,
+/// so this fails with `MoabError::RequestError` while another one is alive on the same thread.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{get_sql_context, window::WindowBuilder, window::WindowLength};
+///
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Months(3))
+///     .build()
+///     .unwrap();
+///
+/// let (ctx, _) = get_sql_context(&["AAPL", "MSFT"], window, false, None).unwrap();
+/// let df = ctx
+///     .execute("SELECT timestamp, close FROM AAPL WHERE close > 150")
+///     .unwrap()
+///     .collect()
+///     .unwrap();
+/// println!("{:?}", df);
+/// ```
+///
+#[cfg(feature = "sql")]
+pub fn get_sql_context(
+    tickers: &[impl AsRef<str>],
+    window: window::Window,
+    intraday: bool,
+    credentials: Option<credentials::Credentials>,
+) -> Result<(polars::sql::SQLContext, Vec<String>), errors::MoabError> {
+    client::Client::new(credentials).get_sql_context(tickers, window, intraday)
+}
+
+/// Get the correlation matrix of daily returns across several tickers
+/// # Arguments
+/// * `tickers` - The ticker symbols of the equities
+/// * `window` - The window of time to compute the correlation over
+/// * `credentials` - The credentials to use to authenticate the requests. If None, the requests will be unauthenticated
+/// * `value_col` - The price column to compute returns from, such as the close
+///
+/// # Returns
+/// An NxN frame with a `symbol` column naming each row and one column per ticker, see `frame::correlation`.
+/// Tickers whose request fails are left out. Missing data is handled with pairwise deletion:
+/// each pair of tickers is correlated over the days both have returns.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{correlation_matrix, window::WindowBuilder, window::WindowLength};
+///
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Years(1))
+///     .build()
+///     .unwrap();
+///
+/// let corr = correlation_matrix(&["AAPL", "MSFT", "GOOG"], window, None, "close").unwrap();
+/// println!("{:?}", corr);
+/// ```
+///
+pub fn correlation_matrix(
+    tickers: &[impl AsRef<str>],
+    window: window::Window,
+    credentials: Option<credentials::Credentials>,
+    value_col: &str,
+) -> Result<DataFrame, errors::MoabError> {
+    client::Client::new(credentials).correlation_matrix(tickers, window, value_col)
+}
+
+/// Fetch equity data and append it to an existing frame in place, for accumulating many fetches
+/// without reallocating the frame each time
+/// # Arguments
+/// * `target` - The frame to append to. An empty frame takes the fetched data as it is
+/// * `ticker` - The ticker symbol of the equity
+/// * `window` - The window of time to get data for. Build a window with the `WindowBuilder`
+/// * `intraday` - Whether to get intraday data or daily data
+/// * `credentials` - The credentials to use to authenticate the request. If None, the request will be unauthenticated
+///
+/// # Returns
+/// `MoabError::DecodeError` if the fetched data's columns don't match the frame's, see `frame::extend`.
+/// The frame is left as it was if the request or the append fails.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{extend_frame, window::WindowBuilder, window::WindowLength};
+/// use polars::prelude::DataFrame;
+///
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Years(1))
+///     .build()
+///     .unwrap();
+///
+/// let mut df = DataFrame::default();
+/// for ticker in ["AAPL", "MSFT", "GOOG"] {
+///     extend_frame(&mut df, ticker, window, false, None).unwrap();
+/// }
+/// df.rechunk();
+/// println!("{:?}", df);
+/// ```
+///
+pub fn extend_frame(
+    target: &mut DataFrame,
+    ticker: impl AsRef<str>,
+    window: window::Window,
+    intraday: bool,
+    credentials: Option<credentials::Credentials>,
+) -> Result<(), errors::MoabError> {
+    client::Client::new(credentials).extend_frame(target, ticker, window, intraday)
+}
+
+/// Compute a single value, such as the VWAP or the average volume, over a window of daily equity data.
+/// The server has no aggregation requests, so the bars are fetched and aggregated locally.
+/// # Arguments
+/// * `ticker` - The ticker symbol of the equity
+/// * `window` - The window of time to aggregate over. Build a window with the `WindowBuilder`
+/// * `agg` - The value to compute, see `frame::Aggregation`
+/// * `credentials` - The credentials to use to authenticate the request. If None, the request will be unauthenticated
+///
+/// # Returns
+/// The value, or `MoabError::NotFound` if the window has no bars to compute it from
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{frame::Aggregation, get_aggregate, window::WindowBuilder, window::WindowLength};
+///
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Months(1))
+///     .build()
+///     .unwrap();
+///
+/// let vwap = get_aggregate("AAPL", window, Aggregation::Vwap, None).unwrap();
+/// println!("{}", vwap);
+/// ```
+///
+pub fn get_aggregate(
+    ticker: impl AsRef<str>,
+    window: window::Window,
+    agg: frame::Aggregation,
+    credentials: Option<credentials::Credentials>,
+) -> Result<f64, errors::MoabError> {
+    client::Client::new(credentials).get_aggregate(ticker, window, &agg)
+}
+
+/// Write the equity data for a given ticker as CSV
+/// # Arguments
+/// * `ticker` - The ticker symbol of the equity
+/// * `window` - The window of time to get data for. Build a window with the `WindowBuilder`
+/// * `intraday` - Whether to get intraday data or daily data
+/// * `credentials` - The credentials to use to authenticate the request. If None, the request will be unauthenticated
+/// * `writer` - Where to write the CSV, such as a file or stdout
+///
+/// # Returns
+/// The number of rows written. The CSV is written and flushed in batches, so memory use
+/// doesn't grow with the size of the CSV text.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{write_equity_csv, window::WindowBuilder, window::WindowLength};
+///
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Years(1))
+///     .build()
+///     .unwrap();
+///
+/// let mut file = std::fs::File::create("aapl.csv").unwrap();
+/// let rows = write_equity_csv("AAPL", window, true, None, &mut file).unwrap();
+/// println!("Wrote {} rows", rows);
+/// ```
+///
+pub fn write_equity_csv(
+    ticker: impl AsRef<str>,
+    window: window::Window,
+    intraday: bool,
+    credentials: Option<credentials::Credentials>,
+    writer: &mut impl std::io::Write,
+) -> Result<usize, errors::MoabError> {
+    client::Client::new(credentials).write_equity_csv(ticker, window, intraday, writer)
+}
+
+/// Write the equity data for a given ticker as an Arrow IPC file, also known as Feather v2,
+/// for handing the frame to another process that can memory map it instead of decoding it
+/// # Arguments
+/// * `ticker` - The ticker symbol of the equity
+/// * `window` - The window of time to get data for. Build a window with the `WindowBuilder`
+/// * `intraday` - Whether to get intraday data or daily data
+/// * `credentials` - The credentials to use to authenticate the request. If None, the request will be unauthenticated
+/// * `writer` - Where to write the file
+///
+/// # Returns
+/// The number of rows written. The file is uncompressed and reads back with the same schema
+/// as the frame `get_equity` returns, see `frame::write_ipc`.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{write_equity_ipc, window::WindowBuilder, window::WindowLength};
+///
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Years(1))
+///     .build()
+///     .unwrap();
+///
+/// let mut file = std::fs::File::create("aapl.arrow").unwrap();
+/// let rows = write_equity_ipc("AAPL", window, true, None, &mut file).unwrap();
+/// println!("Wrote {} rows", rows);
+/// ```
+///
+#[cfg(feature = "ipc")]
+pub fn write_equity_ipc(
+    ticker: impl AsRef<str>,
+    window: window::Window,
+    intraday: bool,
+    credentials: Option<credentials::Credentials>,
+    writer: &mut impl std::io::Write,
+) -> Result<usize, errors::MoabError> {
+    client::Client::new(credentials).write_equity_ipc(ticker, window, intraday, writer)
+}
+
+/// Get the equity data for a given ticker as one JSON object per row, ready to serialize into
+/// a response body without going through polars' JSON writer
+/// # Arguments
+/// * `ticker` - The ticker symbol of the equity
+/// * `window` - The window of time to get data for. Build a window with the `WindowBuilder`
+/// * `intraday` - Whether to get intraday data or daily data
+/// * `credentials` - The credentials to use to authenticate the request. If None, the request will be unauthenticated
+///
+/// # Returns
+/// The rows keyed by column name, see `frame::to_json_rows` for how each type is converted
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{get_equity_json_rows, window::WindowBuilder, window::WindowLength};
+///
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Days(5))
+///     .build()
+///     .unwrap();
+///
+/// let rows = get_equity_json_rows("AAPL", window, false, None).unwrap();
+/// let body = serde_json::to_string(&rows).unwrap();
+/// ```
+///
+#[cfg(feature = "json")]
+pub fn get_equity_json_rows(
+    ticker: impl AsRef<str>,
+    window: window::Window,
+    intraday: bool,
+    credentials: Option<credentials::Credentials>,
+) -> Result<Vec<serde_json::Value>, errors::MoabError> {
+    client::Client::new(credentials).get_equity_json_rows(ticker, window, intraday)
+}
+
+/// Get the undecoded response to an equity request, to capture fixtures or debug the protocol
+/// # Arguments
+/// * `ticker` - The ticker symbol of the equity
+/// * `window` - The window of time to get data for. Build a window with the `WindowBuilder`
+/// * `intraday` - Whether to get intraday data or daily data
+/// * `credentials` - The credentials to use to authenticate the request. If None, the request will be unauthenticated
+///
+/// # Returns
+/// The protobuf encoded `Response` exactly as the server sent it, decoded from base64 but with
+/// its status code unchecked and its parquet data still encoded, even if it can't be decoded.
+/// A response the server sends as plain parquet is returned as those parquet bytes.
+/// Pass either to `decode_response_bytes` to decode it later.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{get_equity_raw_response, window::WindowBuilder, window::WindowLength};
+///
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Days(5))
+///     .build()
+///     .unwrap();
+///
+/// let bytes = get_equity_raw_response("AAPL", window, false, None).unwrap();
+/// std::fs::write("aapl_response.pb", &bytes).unwrap();
+/// let df = moabdb::decode_response_bytes(&bytes).unwrap();
+/// ```
+///
+pub fn get_equity_raw_response(
+    ticker: impl AsRef<str>,
+    window: window::Window,
+    intraday: bool,
+    credentials: Option<credentials::Credentials>,
+) -> Result<Vec<u8>, errors::MoabError> {
+    client::Client::new(credentials).get_equity_raw_response(ticker, window, intraday)
+}
+
+/// Get the equity data for a given ticker as a lazy scan over the received parquet, so a query's
+/// projections and leading slices are pushed into the parquet reader and the columns it doesn't
+/// use are never decoded. Filters are applied to the projected columns as they're read.
+/// Unlike collecting `get_equity` into a lazy frame, the data is only decoded when the query runs.
+/// # Arguments
+/// * `ticker` - The ticker symbol of the equity
+/// * `window` - The window of time to get data for. Build a window with the `WindowBuilder`
+/// * `intraday` - Whether to get intraday data or daily data
+/// * `credentials` - The credentials to use to authenticate the request. If None, the request will be unauthenticated
+///
+/// # Returns
+/// A `LazyFrame` over the data exactly as the server sent it. The client's output options,
+/// such as `Client::with_canonical_columns`, aren't applied.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{get_equity_scan, window::WindowBuilder, window::WindowLength};
+/// use polars::prelude::*;
+///
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Years(1))
+///     .build()
+///     .unwrap();
+///
+/// let df = get_equity_scan("AAPL", window, true, None)
+///     .unwrap()
+///     .filter(col("volume").gt(lit(1_000_000)))
+///     .select([col("timestamp"), col("close")])
+///     .collect()
+///     .unwrap();
+/// println!("{:?}", df);
+/// ```
+///
+#[cfg(feature = "lazy")]
+pub fn get_equity_scan(
+    ticker: impl AsRef<str>,
+    window: window::Window,
+    intraday: bool,
+    credentials: Option<credentials::Credentials>,
+) -> Result<polars::prelude::LazyFrame, errors::MoabError> {
+    client::Client::new(credentials).get_equity_scan(ticker, window, intraday)
+}
+
+/// Decode a captured base64 response body into its data, without any network access
+/// # Arguments
+/// * `response` - The base64 response body exactly as returned by the server, or a plain parquet body
+///
+/// # Returns
+/// A `DataFrame` containing the data, or the error the server's status code maps to
+///
+/// # Examples
+/// ```no_run
+/// let body = std::fs::read_to_string("aapl_response.b64").unwrap();
+/// let df = moabdb::decode_response(&body).unwrap();
+/// println!("{:?}", df);
+/// ```
+///
+pub fn decode_response(response: &str) -> Result<DataFrame, errors::MoabError> {
+    if client::is_parquet(response.as_bytes()) {
+        return decode_response_bytes(response.as_bytes());
     }
-    let resp = match String::from_utf8(buffer) {
-        Ok(resp) => resp,
-        Err(_) => return Err(errors::MoabError::TransportError),
-    };
-
-    let resp: protocol::Response = match resp.try_into() {
-        Ok(resp) => resp,
-        Err(_) => return Err(errors::MoabError::TransportError),
-    };
-
-    match resp.code {
-        200 => (),
-        400 => return Err(errors::MoabError::RequestError),
-        401 => return Err(errors::MoabError::Unauthorized),
-        404 => return Err(errors::MoabError::NotFound),
-        500 => return Err(errors::MoabError::ServerInternalError),
-        _ => return Err(errors::MoabError::UnknownError),
+    match protocol::Response::try_from(response.to_string()) {
+        Ok(resp) => client::decode(resp),
+        Err(_) => Err(errors::MoabError::DecodeError),
     }
-    let df = ParquetReader::new(std::io::Cursor::new(resp.data));
-    let df = match df.finish() {
-        Ok(df) => df,
-        Err(_) => return Err(errors::MoabError::TransportError),
-    };
+}
 
-    Ok(df)
+/// Decode a captured response, already decoded from base64 into protobuf bytes, into its data
+/// # Arguments
+/// * `response` - The protobuf encoded response, or a plain parquet body, as returned by `get_equity_raw_response`
+///
+/// # Returns
+/// A `DataFrame` containing the data, or the error the server's status code maps to
+pub fn decode_response_bytes(response: &[u8]) -> Result<DataFrame, errors::MoabError> {
+    if client::is_parquet(response) {
+        return client::decode(client::wrap_parquet(response.to_vec()));
+    }
+    match protocol::Response::deserialize(response) {
+        Ok(resp) => client::decode(resp),
+        Err(_) => Err(errors::MoabError::DecodeError),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use polars::prelude::NamedFrom;
 
     #[test]
     fn equity_sync() {
@@ -112,4 +848,111 @@ mod tests {
         let df = get_equity("AAPL", window, false, None).unwrap();
         println!("{:?}", df);
     }
+
+    fn captured_response(code: u32, df: &mut DataFrame) -> String {
+        use polars::prelude::ParquetWriter;
+
+        let mut data = Vec::new();
+        ParquetWriter::new(&mut data).finish(df).unwrap();
+        encode_response(code, data)
+    }
+
+    fn encode_response(code: u32, data: Vec<u8>) -> String {
+        encode_versioned_response(code, data, protocol::PROTOCOL_VERSION)
+    }
+
+    fn encode_versioned_response(code: u32, data: Vec<u8>, version: u32) -> String {
+        use prost::Message;
+
+        let resp = protocol::Response {
+            code,
+            message: "".to_string(),
+            data,
+            version,
+        };
+        base64::encode(resp.encode_to_vec())
+    }
+
+    #[test]
+    fn decode_captured_response() {
+        let mut df = polars::df!("timestamp" => &[0i64, 60], "close" => &[1.0, 2.0]).unwrap();
+        let decoded = decode_response(&captured_response(200, &mut df)).unwrap();
+        assert!(decoded.frame_equal(&df));
+    }
+
+    #[test]
+    fn decode_captured_plain_parquet() {
+        use polars::prelude::ParquetWriter;
+
+        let mut df = polars::df!("timestamp" => &[0i64, 60], "close" => &[1.0, 2.0]).unwrap();
+        let mut data = Vec::new();
+        ParquetWriter::new(&mut data).finish(&mut df).unwrap();
+        assert!(decode_response_bytes(&data).unwrap().frame_equal(&df));
+    }
+
+    #[test]
+    fn decode_captured_error() {
+        let mut df = DataFrame::default();
+        let decoded = decode_response(&captured_response(404, &mut df));
+        assert!(matches!(decoded, Err(errors::MoabError::NotFound)));
+        let unknown = protocol::Response {
+            code: 451,
+            message: "Unavailable for legal reasons".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            decode_response_bytes(&prost::Message::encode_to_vec(&unknown)),
+            Err(errors::MoabError::UnknownError(451, message)) if message == "Unavailable for legal reasons"
+        ));
+        let decoded = decode_response(&captured_response(503, &mut df));
+        assert!(matches!(
+            decoded,
+            Err(errors::MoabError::Maintenance { retry_after: None })
+        ));
+        assert!(matches!(
+            decode_response("not base64!"),
+            Err(errors::MoabError::DecodeError)
+        ));
+    }
+
+    #[test]
+    fn decode_checks_the_protocol_version() {
+        use polars::prelude::ParquetWriter;
+
+        let mut df = polars::df!("timestamp" => &[0i64, 60]).unwrap();
+        let mut data = Vec::new();
+        ParquetWriter::new(&mut data).finish(&mut df).unwrap();
+        let unversioned = encode_versioned_response(200, data.clone(), 0);
+        assert!(decode_response(&unversioned).unwrap().frame_equal(&df));
+        let newer = encode_versioned_response(200, data, protocol::PROTOCOL_VERSION + 1);
+        assert!(matches!(
+            decode_response(&newer),
+            Err(errors::MoabError::UnsupportedProtocol(v)) if v == protocol::PROTOCOL_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn decode_malformed_parquet() {
+        use polars::prelude::ParquetWriter;
+
+        let mut df = polars::df!("timestamp" => &[0i64, 60], "close" => &[1.0, 2.0]).unwrap();
+        let mut data = Vec::new();
+        ParquetWriter::new(&mut data).finish(&mut df).unwrap();
+        // Corrupts the first page header, which the reader panics on
+        data[7] = 0;
+        assert!(matches!(
+            decode_response(&encode_response(200, data.clone())),
+            Err(errors::MoabError::DecodeError)
+        ));
+        // Without its footer the reader returns an error rather than panicking
+        data.truncate(data.len() - 8);
+        assert!(matches!(
+            decode_response(&encode_response(200, data.clone())),
+            Err(errors::MoabError::DecodeError)
+        ));
+        assert!(matches!(
+            decode_response_bytes(&data),
+            Err(errors::MoabError::DecodeError)
+        ));
+    }
 }
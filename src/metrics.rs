@@ -0,0 +1,48 @@
+// Jackson Coxson
+
+use std::time::Duration;
+
+/// A hook for recording request metrics, such as Prometheus counters and latency histograms.
+/// Register one on a `Client` with `Client::with_metrics`.
+///
+/// # Examples
+/// ```
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use moabdb::{client::Client, metrics::{Metrics, RequestEvent}};
+///
+/// #[derive(Default)]
+/// struct Counter(AtomicUsize);
+///
+/// impl Metrics for Counter {
+///     fn record(&self, _event: RequestEvent) {
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let client = Client::new(None).with_metrics(Counter::default());
+/// ```
+pub trait Metrics: Send + Sync {
+    /// Called once for every request the client makes, after it completes
+    fn record(&self, event: RequestEvent);
+}
+
+/// A single completed request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestEvent {
+    /// The symbol that was requested
+    pub symbol: String,
+    /// The datatype that was requested, such as `daily_stocks`
+    pub datatype: String,
+    /// How long the request took, including decoding the response
+    pub duration: Duration,
+    /// The status code returned by the server, if a response was received
+    pub code: Option<u32>,
+    /// Whether the request returned data
+    pub success: bool,
+    /// Whether this was a fallback request made in place of the one asked for: an unauthenticated
+    /// retry after the client's credentials were rejected, see `Client::with_fallback_unauthenticated`,
+    /// or daily data in place of missing intraday data, see `Client::with_intraday_fallback`
+    pub degraded: bool,
+    /// The tag the request was made under, see `tag::with_tag`
+    pub tag: Option<String>,
+}
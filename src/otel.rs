@@ -0,0 +1,58 @@
+// Jackson Coxson
+
+use std::collections::HashMap;
+
+use opentelemetry::{
+    global,
+    trace::{SpanKind, Status, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+
+/// The OpenTelemetry client span around a single request, a child of the caller's current context
+pub(crate) struct RequestSpan {
+    cx: Context,
+}
+
+impl RequestSpan {
+    pub(crate) fn start(symbol: &str, datatype: &str) -> Self {
+        let tracer = global::tracer("moabdb");
+        let span = tracer
+            .span_builder("moabdb.request")
+            .with_kind(SpanKind::Client)
+            .with_attributes(
+                vec![
+                    KeyValue::new("moabdb.symbol", symbol.to_string()),
+                    KeyValue::new("moabdb.datatype", datatype.to_string()),
+                ]
+                .into_iter()
+                .chain(crate::tag::current().map(|tag| KeyValue::new("moabdb.tag", tag)))
+                .collect::<Vec<_>>(),
+            )
+            .start_with_context(&tracer, &Context::current());
+        Self {
+            cx: Context::current_with_span(span),
+        }
+    }
+
+    /// The headers that propagate the span's trace context, such as `traceparent`,
+    /// as written by the global text map propagator
+    pub(crate) fn headers(&self) -> HashMap<String, String> {
+        let mut headers = HashMap::new();
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&self.cx, &mut headers)
+        });
+        headers
+    }
+
+    /// End the span, recording the response's status code and whether the request succeeded
+    pub(crate) fn end(&self, code: Option<u32>, success: bool) {
+        let span = self.cx.span();
+        if let Some(code) = code {
+            span.set_attribute(KeyValue::new("moabdb.code", i64::from(code)));
+        }
+        if !success {
+            span.set_status(Status::error("request failed"));
+        }
+        span.end();
+    }
+}
@@ -6,11 +6,13 @@ use prost::Message;
 
 include!(concat!(env!("OUT_DIR"), "/_.rs"));
 
+/// The protocol version sent with every request and accepted in responses
+pub const PROTOCOL_VERSION: u32 = 1;
+
 impl Request {
     pub fn serialize(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(self.encoded_len());
-        self.encode(&mut buf).unwrap();
-        buf
+        // Encoding into a Vec grows it as needed, so unlike encode this can't fail
+        self.encode_to_vec()
     }
     pub fn b64(&self) -> String {
         base64::encode(self.serialize())
@@ -18,7 +20,13 @@ impl Request {
 }
 
 impl Response {
-    fn deserialize(bytes: &[u8]) -> Result<Response, prost::DecodeError> {
+    /// Whether the response is in a protocol version this crate can read.
+    /// A response without a version is from a server that predates versioning,
+    /// whose format is version 1.
+    pub fn version_supported(&self) -> bool {
+        self.version == 0 || self.version == PROTOCOL_VERSION
+    }
+    pub fn deserialize(bytes: &[u8]) -> Result<Response, prost::DecodeError> {
         Response::decode(bytes)
     }
 }
@@ -33,3 +41,39 @@ impl TryFrom<String> for Response {
         Response::deserialize(&bytes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::WindowBuilder;
+
+    fn round_trip(start: i64, end: i64) -> Request {
+        let window = WindowBuilder::new()
+            .start_timestamp(start)
+            .end_timestamp(end)
+            .build()
+            .unwrap();
+        let (start, end) = window.request_timestamps();
+        let req = Request {
+            symbol: "AAPL".to_string(),
+            start,
+            end,
+            ..Default::default()
+        };
+        Request::decode(req.serialize().as_slice()).unwrap()
+    }
+
+    #[test]
+    fn pre_1970_timestamps_survive_encoding() {
+        let req = round_trip(-86400 * 365 * 50, -86400);
+        assert_eq!(req.start, -86400 * 365 * 50);
+        assert_eq!(req.end, -86400);
+    }
+
+    #[test]
+    fn post_2106_timestamps_survive_encoding() {
+        let req = round_trip(0, 5_000_000_000);
+        assert_eq!(req.start, 0);
+        assert_eq!(req.end, 5_000_000_000);
+    }
+}
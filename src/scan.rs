@@ -0,0 +1,84 @@
+// Jackson Coxson
+
+use std::{io::Cursor, sync::Arc};
+
+use polars::prelude::{
+    AnonymousScan, AnonymousScanOptions, DataFrame, LazyFrame, ParquetReader, PolarsError,
+    PolarsResult, ScanArgsAnonymous, Schema, SerReader,
+};
+
+/// Parquet data held in memory, scanned lazily so only the columns and rows a query uses are decoded
+struct ParquetBytes {
+    data: Vec<u8>,
+}
+
+impl AnonymousScan for ParquetBytes {
+    fn scan(&self, options: AnonymousScanOptions) -> PolarsResult<DataFrame> {
+        let columns = options.with_columns.map(|columns| columns.as_ref().clone());
+        // The parquet reader can panic on malformed data, which shouldn't take down the caller
+        std::panic::catch_unwind(|| {
+            ParquetReader::new(Cursor::new(self.data.as_slice()))
+                .with_columns(columns)
+                .with_n_rows(options.n_rows)
+                .finish()
+        })
+        .unwrap_or_else(|_| Err(PolarsError::ComputeError("Malformed parquet data".into())))
+    }
+
+    fn allows_projection_pushdown(&self) -> bool {
+        true
+    }
+
+    fn allows_slice_pushdown(&self) -> bool {
+        true
+    }
+}
+
+/// A lazy frame over parquet data, reading only its schema up front.
+/// Projections and leading slices are pushed into the parquet reader, and filters
+/// are applied to the projected columns as they're read.
+pub(crate) fn scan_parquet(data: Vec<u8>) -> PolarsResult<LazyFrame> {
+    let schema: Schema =
+        std::panic::catch_unwind(|| ParquetReader::new(Cursor::new(data.as_slice())).schema())
+            .unwrap_or_else(|_| Err(PolarsError::ComputeError("Malformed parquet data".into())))?;
+    let args = ScanArgsAnonymous {
+        schema: Some(schema),
+        name: "MOABDB PARQUET SCAN",
+        ..Default::default()
+    };
+    LazyFrame::anonymous_scan(Arc::new(ParquetBytes { data }), args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::{col, lit, IntoLazy, NamedFrom, ParquetWriter};
+
+    #[test]
+    fn scans_match_eager_queries() {
+        let mut df = polars::df!(
+            "timestamp" => &[0i64, 60, 120],
+            "close" => &[1.0, 2.0, 3.0],
+            "volume" => &[10i64, 20, 30],
+        )
+        .unwrap();
+        let mut data = Vec::new();
+        ParquetWriter::new(&mut data).finish(&mut df).unwrap();
+
+        let query = |lf: LazyFrame| {
+            lf.filter(col("close").gt(lit(1.5)))
+                .select([col("timestamp")])
+                .collect()
+                .unwrap()
+        };
+        let scanned = query(scan_parquet(data.clone()).unwrap());
+        assert!(scanned.frame_equal(&query(df.clone().lazy())));
+        let head = scan_parquet(data).unwrap().limit(1).collect().unwrap();
+        assert!(head.frame_equal(&df.head(Some(1))));
+    }
+
+    #[test]
+    fn malformed_data_is_an_error() {
+        assert!(scan_parquet(b"PAR1 not really".to_vec()).is_err());
+    }
+}
@@ -0,0 +1,68 @@
+// Jackson Coxson
+
+use std::cell::RefCell;
+
+thread_local! {
+    static TAG: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Attach `tag` to every request made while `f` runs on this thread, including requests a batch
+/// such as `get_equities` spreads across worker threads. The tag is an opaque label for tying
+/// requests back to the job that made them. It's never sent to the server, but it's passed to
+/// interceptors and metrics and recorded on OpenTelemetry spans. Tags nest, with the innermost
+/// one winning, and the previous tag is restored when `f` returns or panics.
+/// Identical requests in flight at once are still only sent once, so the request that's actually
+/// made carries the tag of whichever caller made it first.
+///
+/// # Examples
+/// ```no_run
+/// use moabdb::{client::Client, tag, window::WindowBuilder, window::WindowLength};
+///
+/// let client = Client::new(None);
+/// let window = WindowBuilder::new()
+///     .length(WindowLength::Months(3))
+///     .build()
+///     .unwrap();
+///
+/// let df = tag::with_tag("job-123", || client.get_equity("AAPL", window, false));
+/// ```
+pub fn with_tag<T>(tag: impl AsRef<str>, f: impl FnOnce() -> T) -> T {
+    scoped(Some(tag.as_ref().to_string()), f)
+}
+
+/// The tag attached to requests made from this thread, if any, see `with_tag`
+pub fn current() -> Option<String> {
+    TAG.with(|tag| tag.borrow().clone())
+}
+
+/// Run `f` with the tag set to `tag`, restoring the previous tag afterwards
+pub(crate) fn scoped<T>(tag: Option<String>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<String>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            TAG.with(|tag| *tag.borrow_mut() = previous);
+        }
+    }
+
+    let _restore = Restore(TAG.with(|current| current.replace(tag)));
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_nest_and_restore() {
+        assert_eq!(current(), None);
+        with_tag("outer", || {
+            with_tag("inner", || assert_eq!(current().as_deref(), Some("inner")));
+            assert_eq!(current().as_deref(), Some("outer"));
+        });
+        let panicked = std::panic::catch_unwind(|| with_tag("panics", || panic!("job failed")));
+        assert!(panicked.is_err());
+        assert_eq!(current(), None);
+    }
+}
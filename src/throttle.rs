@@ -0,0 +1,107 @@
+// Jackson Coxson
+
+use std::{
+    io::Read,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The most bytes read from a response between checks of the bandwidth limit
+const MAX_CHUNK_BYTES: usize = 16 * 1024;
+
+/// Caps how fast response bodies are read, see `Client::with_bandwidth_limit`.
+/// A token bucket holding up to a second of bytes, shared by every request the client makes.
+pub(crate) struct Throttle {
+    bytes_per_second: f64,
+    chunk: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// Bytes that can be read without waiting, negative once readers have gone over the limit
+    tokens: f64,
+    /// When `tokens` was last topped up
+    refilled: Instant,
+}
+
+impl Throttle {
+    pub(crate) fn new(bytes_per_second: u64) -> Self {
+        let bytes_per_second = bytes_per_second.max(1);
+        Self {
+            bytes_per_second: bytes_per_second as f64,
+            chunk: usize::try_from(bytes_per_second)
+                .map_or(MAX_CHUNK_BYTES, |b| b.min(MAX_CHUNK_BYTES)),
+            state: Mutex::new(State {
+                tokens: bytes_per_second as f64,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take `bytes` from the bucket, returning how long to wait before reading more
+    /// so the reads so far stay under the limit
+    fn take(&self, bytes: usize) -> Duration {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(e) => e.into_inner(),
+        };
+        let now = Instant::now();
+        let refill = now.duration_since(state.refilled).as_secs_f64() * self.bytes_per_second;
+        state.tokens = (state.tokens + refill).min(self.bytes_per_second) - bytes as f64;
+        state.refilled = now;
+        if state.tokens < 0.0 {
+            Duration::from_secs_f64(-state.tokens / self.bytes_per_second)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// Read `reader` to the end into `buffer`, sleeping between chunks to stay under the limit
+    pub(crate) fn read_to_end(
+        &self,
+        reader: &mut impl Read,
+        buffer: &mut Vec<u8>,
+    ) -> std::io::Result<usize> {
+        let mut chunk = vec![0; self.chunk];
+        let mut total = 0;
+        loop {
+            let n = match reader.read(&mut chunk) {
+                Ok(0) => return Ok(total),
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            buffer.extend_from_slice(&chunk[..n]);
+            total += n;
+            let wait = self.take(n);
+            if !wait.is_zero() {
+                std::thread::sleep(wait);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bursts_up_to_a_second_then_waits() {
+        let throttle = Throttle::new(1000);
+        assert_eq!(throttle.take(1000), Duration::ZERO);
+        let wait = throttle.take(500);
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn reads_everything_in_chunks() {
+        let throttle = Throttle::new(1 << 20);
+        let body = vec![7u8; 100_000];
+        let mut buffer = Vec::new();
+        let read = throttle
+            .read_to_end(&mut body.as_slice(), &mut buffer)
+            .unwrap();
+        assert_eq!(read, body.len());
+        assert_eq!(buffer, body);
+    }
+}
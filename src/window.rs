@@ -1,11 +1,564 @@
 // Jackson Coxson
 
-use chrono::NaiveDateTime;
+use chrono::{Datelike, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Window {
     pub start: NaiveDateTime,
     pub end: NaiveDateTime,
+    /// Whether data at exactly `end` is included in the window
+    pub bound: Bound,
+}
+
+/// Whether the end of a window is included in it.
+/// Windows are inclusive of their end by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Bound {
+    /// Include data at exactly the end time, `[start, end]`
+    #[default]
+    Inclusive,
+    /// Exclude data at exactly the end time, `[start, end)`
+    Exclusive,
+}
+
+/// A market with a regular trading session, for `Window::session`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Market {
+    /// The New York Stock Exchange, 9:30 to 16:00 New York time
+    Nyse,
+    /// The Nasdaq Stock Market, 9:30 to 16:00 New York time
+    Nasdaq,
+}
+
+impl Market {
+    /// The local opening and closing times of the regular session
+    pub fn hours(&self) -> (NaiveTime, NaiveTime) {
+        match self {
+            Market::Nyse | Market::Nasdaq => (
+                NaiveTime::from_hms_opt(9, 30, 0).unwrap_or(NaiveTime::MIN),
+                NaiveTime::from_hms_opt(16, 0, 0).unwrap_or(NaiveTime::MIN),
+            ),
+        }
+    }
+
+    /// The local times extended hours trading starts before the open and ends after the close
+    pub fn extended_hours(&self) -> (NaiveTime, NaiveTime) {
+        match self {
+            Market::Nyse | Market::Nasdaq => (
+                NaiveTime::from_hms_opt(4, 0, 0).unwrap_or(NaiveTime::MIN),
+                NaiveTime::from_hms_opt(20, 0, 0).unwrap_or(NaiveTime::MIN),
+            ),
+        }
+    }
+
+    /// The market's offset from UTC in hours on `date`
+    pub fn utc_offset(&self, date: NaiveDate) -> i64 {
+        match self {
+            Market::Nyse | Market::Nasdaq => {
+                if us_daylight_time(date) {
+                    -4
+                } else {
+                    -5
+                }
+            }
+        }
+    }
+}
+
+/// A part of a market's trading day, for `Client::with_session`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Session {
+    /// From the start of extended hours until the open, 4:00 to 9:30 New York time
+    PreMarket,
+    /// From the open until the close, 9:30 to 16:00 New York time
+    Regular,
+    /// From the close until the end of extended hours, 16:00 to 20:00 New York time
+    AfterHours,
+    /// Every bar, including any outside extended hours
+    #[default]
+    All,
+}
+
+impl Session {
+    /// Whether a bar starting at `utc` falls in this session of `market`, in the market's local
+    /// time on that day, accounting for daylight saving time. Each session includes its start
+    /// and excludes its end, so a bar at the open is regular and a bar at the close is after hours.
+    /// ```
+    /// use chrono::NaiveDate;
+    /// use moabdb::window::{Market, Session};
+    /// // 9:00 in New York, during daylight time
+    /// let utc = NaiveDate::from_ymd_opt(2023, 7, 3).unwrap().and_hms_opt(13, 0, 0).unwrap();
+    /// assert!(Session::PreMarket.contains(utc, Market::Nyse));
+    /// assert!(!Session::Regular.contains(utc, Market::Nyse));
+    /// ```
+    pub fn contains(&self, utc: NaiveDateTime, market: Market) -> bool {
+        let local = utc + chrono::Duration::hours(market.utc_offset(utc.date()));
+        let time = local.time();
+        let (open, close) = market.hours();
+        let (early, late) = market.extended_hours();
+        match self {
+            Session::PreMarket => time >= early && time < open,
+            Session::Regular => time >= open && time < close,
+            Session::AfterHours => time >= close && time < late,
+            Session::All => true,
+        }
+    }
+}
+
+/// Whether US daylight saving time is in effect during the day on `date`.
+/// Uses the rules in effect since 2007 and the 1987 rules for earlier years.
+fn us_daylight_time(date: NaiveDate) -> bool {
+    let nth_sunday = |month: u32, n: u8| {
+        NaiveDate::from_weekday_of_month_opt(date.year(), month, Weekday::Sun, n)
+    };
+    let last_sunday = |month: u32| {
+        (1..=5)
+            .rev()
+            .find_map(|n| NaiveDate::from_weekday_of_month_opt(date.year(), month, Weekday::Sun, n))
+    };
+    let (start, end) = if date.year() >= 2007 {
+        (nth_sunday(3, 2), nth_sunday(11, 1))
+    } else {
+        (nth_sunday(4, 1), last_sunday(10))
+    };
+    match (start, end) {
+        (Some(start), Some(end)) => date >= start && date < end,
+        _ => false,
+    }
+}
+
+impl Window {
+    /// The start and end timestamps to send to the server, which treats the end as inclusive.
+    /// An exclusive end is pulled back by one second, the resolution of the protocol.
+    pub(crate) fn request_timestamps(&self) -> (i64, i64) {
+        let start = self.start.and_utc().timestamp();
+        let end = self.end.and_utc().timestamp();
+        match self.bound {
+            Bound::Inclusive => (start, end),
+            Bound::Exclusive => (start, end - 1),
+        }
+    }
+
+    /// The window from the start of the current calendar year in `tz` until now
+    /// ```
+    /// use chrono::Datelike;
+    /// use moabdb::window::Window;
+    /// let window = Window::ytd(&chrono::Utc);
+    /// assert_eq!((window.start.month(), window.start.day()), (1, 1));
+    /// ```
+    pub fn ytd<Tz: TimeZone>(tz: &Tz) -> Self {
+        let today = Utc::now().with_timezone(tz).date_naive();
+        Self::since(tz, today.with_ordinal(1).unwrap_or(today))
+    }
+
+    /// The window from the start of the current calendar quarter in `tz` until now
+    pub fn qtd<Tz: TimeZone>(tz: &Tz) -> Self {
+        let today = Utc::now().with_timezone(tz).date_naive();
+        let month = (today.month0() / 3) * 3 + 1;
+        Self::since(
+            tz,
+            NaiveDate::from_ymd_opt(today.year(), month, 1).unwrap_or(today),
+        )
+    }
+
+    /// The window from the start of the current calendar month in `tz` until now
+    pub fn mtd<Tz: TimeZone>(tz: &Tz) -> Self {
+        let today = Utc::now().with_timezone(tz).date_naive();
+        Self::since(tz, today.with_day(1).unwrap_or(today))
+    }
+
+    /// The trailing twelve months, from this time of day twelve calendar months ago until now
+    pub fn ttm() -> Self {
+        let end = Utc::now().naive_utc();
+        let start = end.checked_sub_months(Months::new(12)).unwrap_or(end);
+        Window {
+            start,
+            end,
+            bound: Bound::Inclusive,
+        }
+    }
+
+    /// The regular session of `market` on `date`, from the open to the close in UTC, accounting
+    /// for daylight saving time. Holidays and early closes aren't known, so the window for a day
+    /// the market is closed simply has no data.
+    /// ```
+    /// use chrono::NaiveDate;
+    /// use moabdb::window::{Market, Window};
+    /// let window = Window::session(NaiveDate::from_ymd_opt(2023, 7, 3).unwrap(), Market::Nyse);
+    /// assert_eq!(window.to_string(), "2023-07-03T13:30 .. 2023-07-03T20:00 (6h 30m)");
+    /// ```
+    pub fn session(date: NaiveDate, market: Market) -> Self {
+        let (open, close) = market.hours();
+        let offset = chrono::Duration::hours(market.utc_offset(date));
+        Window {
+            start: date.and_time(open) - offset,
+            end: date.and_time(close) - offset,
+            bound: Bound::Inclusive,
+        }
+    }
+
+    /// Whether the two windows share any time, accounting for exclusive ends
+    /// ```
+    /// use moabdb::window::*;
+    /// let a = Window::try_from((0, 100)).unwrap();
+    /// let b = Window::try_from((100, 200)).unwrap();
+    /// assert!(a.overlaps(&b));
+    /// let a = Window { bound: Bound::Exclusive, ..a };
+    /// assert!(!a.overlaps(&b));
+    /// ```
+    pub fn overlaps(&self, other: &Window) -> bool {
+        let (first, second) = if self.start <= other.start {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        match first.bound {
+            Bound::Inclusive => second.start <= first.end,
+            Bound::Exclusive => second.start < first.end,
+        }
+    }
+
+    /// The union of the two windows, if they overlap or one ends exactly where the other starts
+    /// ```
+    /// use moabdb::window::*;
+    /// let a = Window::try_from((0, 100)).unwrap();
+    /// let b = Window::try_from((50, 200)).unwrap();
+    /// assert_eq!(a.merge(b), Window::try_from((0, 200)).ok());
+    /// assert_eq!(a.merge(Window::try_from((101, 200)).unwrap()), None);
+    /// ```
+    pub fn merge(self, other: Window) -> Option<Window> {
+        let (first, second) = if self.start <= other.start {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        if second.start > first.end {
+            return None;
+        }
+        let bound = match first.end.cmp(&second.end) {
+            std::cmp::Ordering::Greater => first.bound,
+            std::cmp::Ordering::Less => second.bound,
+            std::cmp::Ordering::Equal => match (first.bound, second.bound) {
+                (Bound::Exclusive, Bound::Exclusive) => Bound::Exclusive,
+                _ => Bound::Inclusive,
+            },
+        };
+        Some(Window {
+            start: first.start,
+            end: first.end.max(second.end),
+            bound,
+        })
+    }
+
+    /// The window widened to whole bars of `granularity`, so windows fetched separately line up
+    /// bar for bar. The start is floored to the start of its bar and the end is ceiled to the end of
+    /// the last bar the window touches, giving an exclusive end.
+    /// Bars shorter than a day are counted from the open of `market` in its local time, accounting
+    /// for daylight saving time, and longer bars from local midnight: days from the Unix epoch,
+    /// weeks from a Monday, and months and years from the calendar.
+    /// The window is returned as it is for a granularity that isn't positive.
+    /// ```
+    /// use chrono::NaiveDate;
+    /// use moabdb::window::*;
+    /// let date = NaiveDate::from_ymd_opt(2023, 7, 3).unwrap();
+    /// // 09:31:42 to 09:44:10 New York time
+    /// let window = Window {
+    ///     start: date.and_hms_opt(13, 31, 42).unwrap(),
+    ///     end: date.and_hms_opt(13, 44, 10).unwrap(),
+    ///     bound: Bound::Inclusive,
+    /// };
+    /// let aligned = window.align_to(WindowLength::Minutes(5), Market::Nyse);
+    /// assert_eq!(aligned.to_string(), "2023-07-03T13:30 .. 2023-07-03T13:45 (15m)");
+    /// assert_eq!(aligned.bound, Bound::Exclusive);
+    /// ```
+    pub fn align_to(self, granularity: WindowLength, market: Market) -> Window {
+        let to_local =
+            |utc: NaiveDateTime| utc + chrono::Duration::hours(market.utc_offset(utc.date()));
+        let to_utc =
+            |local: NaiveDateTime| local - chrono::Duration::hours(market.utc_offset(local.date()));
+        let start = bar_start(to_local(self.start), &granularity, market);
+        let end = bar_start(to_local(self.end), &granularity, market).and_then(|last| {
+            if self.bound == Bound::Exclusive && last == to_local(self.end) {
+                Some(last)
+            } else {
+                next_bar(last, &granularity)
+            }
+        });
+        match (start, end) {
+            (Some(start), Some(end)) => Window {
+                start: to_utc(start),
+                end: to_utc(end),
+                bound: Bound::Exclusive,
+            },
+            _ => self,
+        }
+    }
+
+    /// About how many bars of equity data the window holds: one per weekday for daily data,
+    /// or one per minute of the regular session of `market` for intraday data.
+    /// Holidays, early closes and extended hours aren't known, so this is only an estimate,
+    /// and it counts bars in the future as if they already existed.
+    /// ```
+    /// use chrono::NaiveDate;
+    /// use moabdb::window::{Market, Window};
+    /// let date = NaiveDate::from_ymd_opt(2023, 7, 3).unwrap();
+    /// assert_eq!(Window::session(date, Market::Nyse).estimate_rows(true, Market::Nyse), 390);
+    /// assert_eq!(Window::session(date, Market::Nyse).estimate_rows(false, Market::Nyse), 1);
+    /// ```
+    pub fn estimate_rows(&self, intraday: bool, market: Market) -> usize {
+        let to_local =
+            |utc: NaiveDateTime| utc + chrono::Duration::hours(market.utc_offset(utc.date()));
+        // The last time a bar can start at
+        let end = match self.bound {
+            Bound::Inclusive => self.end,
+            Bound::Exclusive => self.end - chrono::Duration::seconds(1),
+        };
+        if end < self.start {
+            return 0;
+        }
+        let trading_days = to_local(self.start)
+            .date()
+            .iter_days()
+            .take_while(|date| *date <= to_local(end).date())
+            .filter(|date| !matches!(date.weekday(), Weekday::Sat | Weekday::Sun));
+        if !intraday {
+            return trading_days.count();
+        }
+        let mut rows = 0;
+        for date in trading_days {
+            let session = Window::session(date, market);
+            let last_bar = session.end - chrono::Duration::minutes(1);
+            let (first, last) = (self.start.max(session.start), end.min(last_bar));
+            if last < first {
+                continue;
+            }
+            // Bars start on every minute from the open
+            let first = ((first - session.start).num_seconds() + 59).div_euclid(60);
+            let last = (last - session.start).num_seconds().div_euclid(60);
+            rows += usize::try_from(last - first + 1).unwrap_or(0);
+        }
+        rows
+    }
+
+    /// The window from midnight of `date` in `tz` until now
+    fn since<Tz: TimeZone>(tz: &Tz, date: NaiveDate) -> Self {
+        let midnight = date.and_time(chrono::NaiveTime::MIN);
+        // Midnight can be skipped by a DST change, in which case the first instant of the day is used
+        let start = match tz.from_local_datetime(&midnight).earliest() {
+            Some(start) => start.naive_utc(),
+            None => Utc.from_utc_datetime(&midnight).naive_utc(),
+        };
+        Window {
+            start,
+            end: Utc::now().naive_utc(),
+            bound: Bound::Inclusive,
+        }
+    }
+}
+
+/// The start of the bar of `granularity` containing the local time `local`, see `Window::align_to`.
+/// None if the granularity isn't positive or the bar is out of range.
+fn bar_start(
+    local: NaiveDateTime,
+    granularity: &WindowLength,
+    market: Market,
+) -> Option<NaiveDateTime> {
+    let floor = |value: i64, n: i64| (n > 0).then(|| value.div_euclid(n) * n);
+    let days_since = |epoch: NaiveDate, n: i64| {
+        let days = floor((local.date() - epoch).num_days(), n)?;
+        epoch.checked_add_signed(chrono::Duration::try_days(days)?)
+    };
+    let date = match *granularity {
+        WindowLength::Seconds(_) | WindowLength::Minutes(_) | WindowLength::Hours(_) => {
+            let step = granularity.duration()?.num_seconds();
+            let open = local.date().and_time(market.hours().0);
+            let bars = floor((local - open).num_seconds(), step)?;
+            return open.checked_add_signed(chrono::Duration::try_seconds(bars)?);
+        }
+        WindowLength::Days(n) => days_since(NaiveDate::from_ymd_opt(1970, 1, 1)?, n)?,
+        WindowLength::Weeks(n) => {
+            days_since(NaiveDate::from_ymd_opt(1970, 1, 5)?, n.checked_mul(7)?)?
+        }
+        WindowLength::Months(n) => {
+            let months = floor(i64::from(local.year()) * 12 + i64::from(local.month0()), n)?;
+            NaiveDate::from_ymd_opt(
+                i32::try_from(months.div_euclid(12)).ok()?,
+                u32::try_from(months.rem_euclid(12)).ok()? + 1,
+                1,
+            )?
+        }
+        WindowLength::Years(n) => {
+            let year = floor(i64::from(local.year()), n)?;
+            NaiveDate::from_ymd_opt(i32::try_from(year).ok()?, 1, 1)?
+        }
+    };
+    Some(date.and_time(NaiveTime::MIN))
+}
+
+/// The start of the bar of `granularity` after the one starting at `bar`
+fn next_bar(bar: NaiveDateTime, granularity: &WindowLength) -> Option<NaiveDateTime> {
+    match *granularity {
+        WindowLength::Months(n) => bar.checked_add_months(Months::new(u32::try_from(n).ok()?)),
+        WindowLength::Years(n) => {
+            bar.checked_add_months(Months::new(u32::try_from(n.checked_mul(12)?).ok()?))
+        }
+        _ => bar.checked_add_signed(granularity.duration()?),
+    }
+}
+
+/// Merge a set of windows into the fewest windows covering the same time, sorted by start.
+/// Windows are merged when they overlap or one ends exactly where the next starts, see `Window::merge`.
+/// ```
+/// use moabdb::window::*;
+/// let windows = vec![
+///     Window::try_from((300, 400)).unwrap(),
+///     Window::try_from((0, 100)).unwrap(),
+///     Window::try_from((50, 200)).unwrap(),
+/// ];
+/// let covered: Vec<(i64, i64)> = coalesce(windows).into_iter().map(Into::into).collect();
+/// assert_eq!(covered, vec![(0, 200), (300, 400)]);
+/// ```
+pub fn coalesce(mut windows: Vec<Window>) -> Vec<Window> {
+    windows.sort_by_key(|window| window.start);
+    let mut merged: Vec<Window> = Vec::with_capacity(windows.len());
+    for window in windows {
+        match merged.last_mut() {
+            Some(last) => match last.merge(window) {
+                Some(union) => *last = union,
+                None => merged.push(window),
+            },
+            None => merged.push(window),
+        }
+    }
+    merged
+}
+
+/// The parts of `requested` not covered by any of `have`, sorted by start, such as the ranges a
+/// cache still has to fetch. The result is empty when `have` covers all of `requested`.
+/// Times are whole seconds, the resolution of the protocol, so a gap after a window with an
+/// inclusive end starts one second after that end. Each gap ends exclusively where the next
+/// covered window starts, and the last keeps the bound of `requested`.
+/// ```
+/// use moabdb::window::*;
+/// let requested = Window::try_from((0, 1000)).unwrap();
+/// let have = vec![
+///     Window::try_from((200, 400)).unwrap(),
+///     Window { bound: Bound::Exclusive, ..Window::try_from((400, 600)).unwrap() },
+/// ];
+/// let gaps = missing_ranges(requested, &have);
+/// assert_eq!(<(i64, i64)>::from(gaps[0]), (0, 200));
+/// assert_eq!(gaps[0].bound, Bound::Exclusive);
+/// assert_eq!(<(i64, i64)>::from(gaps[1]), (600, 1000));
+/// assert_eq!(gaps[1].bound, Bound::Inclusive);
+/// assert!(missing_ranges(requested, &[requested]).is_empty());
+/// ```
+pub fn missing_ranges(requested: Window, have: &[Window]) -> Vec<Window> {
+    let covered = coalesce(
+        have.iter()
+            .filter(|window| window.overlaps(&requested))
+            .copied()
+            .collect(),
+    );
+    let mut missing = Vec::new();
+    // The first time not yet known to be covered
+    let mut cursor = requested.start;
+    for window in covered {
+        if window.start > cursor {
+            missing.push(Window {
+                start: cursor,
+                end: window.start,
+                bound: Bound::Exclusive,
+            });
+        }
+        let after = match window.bound {
+            Bound::Inclusive => window.end + chrono::Duration::seconds(1),
+            Bound::Exclusive => window.end,
+        };
+        cursor = cursor.max(after);
+    }
+    let remaining = match requested.bound {
+        Bound::Inclusive => cursor <= requested.end,
+        Bound::Exclusive => cursor < requested.end,
+    };
+    if remaining {
+        missing.push(Window {
+            start: cursor,
+            ..requested
+        });
+    }
+    missing
+}
+
+/// Prints the window as its start and end with the length between them
+/// ```
+/// use moabdb::window::*;
+/// let window = WindowBuilder::new()
+///     .start_timestamp(0)
+///     .length(WindowLength::Days(180))
+///     .build()
+///     .unwrap();
+/// assert_eq!(window.to_string(), "1970-01-01T00:00 .. 1970-06-30T00:00 (180d)");
+/// ```
+impl std::fmt::Display for Window {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let length = self.end - self.start;
+        let parts = [
+            (length.num_days(), "d"),
+            (length.num_hours() % 24, "h"),
+            (length.num_minutes() % 60, "m"),
+            (length.num_seconds() % 60, "s"),
+        ];
+        let length: Vec<String> = parts
+            .iter()
+            .filter(|(n, _)| *n != 0)
+            .map(|(n, unit)| format!("{n}{unit}"))
+            .collect();
+        let length = if length.is_empty() {
+            "0s".to_string()
+        } else {
+            length.join(" ")
+        };
+        write!(
+            f,
+            "{} .. {} ({})",
+            self.start.format("%Y-%m-%dT%H:%M"),
+            self.end.format("%Y-%m-%dT%H:%M"),
+            length
+        )
+    }
+}
+
+/// The window's start and end as Unix timestamps in seconds, UTC.
+/// The end is the window's own end, regardless of its bound.
+/// ```
+/// use moabdb::window::*;
+/// let window = Window::try_from((0, 86400)).unwrap();
+/// assert_eq!(<(i64, i64)>::from(window), (0, 86400));
+/// ```
+impl From<Window> for (i64, i64) {
+    fn from(window: Window) -> Self {
+        (
+            window.start.and_utc().timestamp(),
+            window.end.and_utc().timestamp(),
+        )
+    }
+}
+
+/// An inclusive window between a start and end Unix timestamp in seconds, UTC.
+/// Fails if either timestamp is out of range or the start is after the end.
+/// ```
+/// use moabdb::window::*;
+/// assert!(Window::try_from((86400, 0)).is_err());
+/// ```
+impl TryFrom<(i64, i64)> for Window {
+    type Error = String;
+
+    fn try_from((start, end): (i64, i64)) -> Result<Self, Self::Error> {
+        WindowBuilder::new()
+            .start_timestamp(start)
+            .end_timestamp(end)
+            .build()
+    }
 }
 
 /// WindowBuilder is an abstraction for creating a Window.
@@ -49,8 +602,7 @@ pub struct Window {
 ///     .length(WindowLength::Days(1))
 ///     .build()
 ///     .unwrap();
-/// assert_eq!(window.start, chrono::Utc::now().naive_utc() - chrono::Duration::days(1));
-/// assert_eq!(window.end, chrono::Utc::now().naive_utc());
+/// assert_eq!(window.end - window.start, chrono::Duration::days(1));
 /// ```
 ///
 /// ### Specify the start and end
@@ -65,10 +617,30 @@ pub struct Window {
 /// assert_eq!(window.start, NaiveDateTime::from_timestamp_opt(0, 0).unwrap());
 /// assert_eq!(window.end, NaiveDateTime::from_timestamp_opt(86400, 0).unwrap());
 /// ```
+///
+/// ### Exclude the end time
+/// ```
+/// use moabdb::window::*;
+/// use chrono::NaiveDateTime;
+/// let window = WindowBuilder::new()
+///     .start(NaiveDateTime::from_timestamp_opt(0, 0).unwrap())
+///     .end(NaiveDateTime::from_timestamp_opt(86400, 0).unwrap())
+///     .bound(Bound::Exclusive)
+///     .build()
+///     .unwrap();
+/// assert_eq!(window.bound, Bound::Exclusive);
+/// ```
 pub struct WindowBuilder {
     pub start: Option<NaiveDateTime>,
     pub end: Option<NaiveDateTime>,
     pub length: Option<WindowLength>,
+    pub bound: Bound,
+    /// The first invalid value passed to the builder, reported by `build`
+    error: Option<String>,
+    /// Whether setting a field twice is an error, see `WindowBuilder::strict`
+    strict: bool,
+    /// The fields set so far, tracked in strict mode
+    set: Vec<&'static str>,
 }
 
 pub enum WindowLength {
@@ -81,6 +653,102 @@ pub enum WindowLength {
     Years(i64),
 }
 
+impl WindowLength {
+    /// Parse an ISO 8601 duration such as `P3M`, `PT15M` or `P1DT12H`.
+    /// A duration with a single component becomes that variant. A duration with several is
+    /// converted into its smallest unit, using 30 day months and 365 day years when years or months
+    /// are combined with days or less, so `P1Y6M` is `Months(18)` and `PT1H30M` is `Minutes(90)`.
+    /// Weeks combined with years or months become days. Fractional values aren't supported.
+    /// ```
+    /// use moabdb::window::*;
+    /// assert!(matches!(WindowLength::from_iso8601("P3M"), Ok(WindowLength::Months(3))));
+    /// assert!(matches!(WindowLength::from_iso8601("PT15M"), Ok(WindowLength::Minutes(15))));
+    /// assert!(matches!(WindowLength::from_iso8601("P1DT12H"), Ok(WindowLength::Hours(36))));
+    /// assert!(WindowLength::from_iso8601("P1.5D").is_err());
+    /// ```
+    pub fn from_iso8601(s: &str) -> Result<Self, String> {
+        let invalid = || format!("{s} is not a supported ISO 8601 duration");
+        let rest = s.strip_prefix('P').ok_or_else(invalid)?;
+        let (date, time) = match rest.split_once('T') {
+            Some((date, time)) if !time.is_empty() => (date, time),
+            Some(_) => return Err(invalid()),
+            None => (rest, ""),
+        };
+
+        // Each component as its unit, indexing Y M W D H M S, and its value
+        let mut parts: Vec<(usize, i64)> = Vec::new();
+        for (section, units, offset) in [(date, "YMWD", 0), (time, "HMS", 4)] {
+            let mut number = String::new();
+            for c in section.chars() {
+                if c.is_ascii_digit() {
+                    number.push(c);
+                    continue;
+                }
+                let unit = units.find(c).ok_or_else(invalid)? + offset;
+                // Components must be in order and appear at most once
+                if parts.last().is_some_and(|(last, _)| unit <= *last) {
+                    return Err(invalid());
+                }
+                parts.push((unit, number.parse().map_err(|_| invalid())?));
+                number.clear();
+            }
+            if !number.is_empty() {
+                return Err(invalid());
+            }
+        }
+
+        let smallest = match parts.last() {
+            Some((unit, _)) => *unit,
+            None => return Err(invalid()),
+        };
+        let total = |unit_lengths: &[i64], target: usize| -> Option<i64> {
+            let mut total: i64 = 0;
+            for (unit, value) in &parts {
+                total = total.checked_add(value.checked_mul(unit_lengths[*unit])?)?;
+            }
+            Some(total / unit_lengths[target])
+        };
+        let length = match smallest {
+            // Years and months combine exactly in months
+            0 | 1 => total(&[12, 1], smallest).map(|n| match smallest {
+                0 => WindowLength::Years(n),
+                _ => WindowLength::Months(n),
+            }),
+            _ => {
+                const SECONDS: [i64; 7] = [365 * 86400, 30 * 86400, 7 * 86400, 86400, 3600, 60, 1];
+                // Weeks don't divide years or months evenly
+                let target = if smallest == 2 && parts.len() > 1 {
+                    3
+                } else {
+                    smallest
+                };
+                total(&SECONDS, target).map(|n| match target {
+                    2 => WindowLength::Weeks(n),
+                    3 => WindowLength::Days(n),
+                    4 => WindowLength::Hours(n),
+                    5 => WindowLength::Minutes(n),
+                    _ => WindowLength::Seconds(n),
+                })
+            }
+        };
+        length.ok_or_else(|| format!("{s} is out of range"))
+    }
+
+    /// The length as a duration, using 30 day months and 365 day years.
+    /// None if the length is too large to represent.
+    pub(crate) fn duration(&self) -> Option<chrono::Duration> {
+        match *self {
+            WindowLength::Seconds(s) => chrono::Duration::try_seconds(s),
+            WindowLength::Minutes(m) => chrono::Duration::try_minutes(m),
+            WindowLength::Hours(h) => chrono::Duration::try_hours(h),
+            WindowLength::Days(d) => chrono::Duration::try_days(d),
+            WindowLength::Weeks(w) => chrono::Duration::try_weeks(w),
+            WindowLength::Months(m) => chrono::Duration::try_days(m.checked_mul(30)?),
+            WindowLength::Years(y) => chrono::Duration::try_days(y.checked_mul(365)?),
+        }
+    }
+}
+
 impl WindowBuilder {
     /// Create a new WindowBuilder
     pub fn new() -> Self {
@@ -88,79 +756,201 @@ impl WindowBuilder {
             start: None,
             end: None,
             length: None,
+            bound: Bound::Inclusive,
+            error: None,
+            strict: false,
+            set: Vec::new(),
+        }
+    }
+    /// Create a new WindowBuilder where `build` fails if any field was set more than once,
+    /// to catch accidental double sets. `start` and `start_timestamp` set the same field, as do
+    /// `end` and `end_timestamp`. A builder from `new` lets the last set win.
+    /// ```
+    /// use moabdb::window::*;
+    /// let window = WindowBuilder::strict()
+    ///     .length(WindowLength::Days(1))
+    ///     .length(WindowLength::Days(2))
+    ///     .build();
+    /// assert!(window.is_err());
+    /// ```
+    pub fn strict() -> Self {
+        Self {
+            strict: true,
+            ..Self::new()
         }
     }
     /// Set the start time of the request window
     pub fn start(mut self, start: NaiveDateTime) -> Self {
+        self.mark_set("start");
         self.start = Some(start);
         self
     }
     /// Set the end time of the request window
     pub fn end(mut self, end: NaiveDateTime) -> Self {
+        self.mark_set("end");
         self.end = Some(end);
         self
     }
+    /// Set the start time of the request window from a Unix timestamp in seconds.
+    /// `build` fails if the timestamp is out of range.
+    /// ```
+    /// use moabdb::window::*;
+    /// let window = WindowBuilder::new()
+    ///     .start_timestamp(0)
+    ///     .end_timestamp(86400)
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(window.end.and_utc().timestamp(), 86400);
+    /// assert!(WindowBuilder::new().start_timestamp(i64::MAX).end_timestamp(0).build().is_err());
+    /// ```
+    pub fn start_timestamp(mut self, start: i64) -> Self {
+        self.mark_set("start");
+        match chrono::DateTime::from_timestamp(start, 0) {
+            Some(start) => self.start = Some(start.naive_utc()),
+            None => self.invalid(format!("Start timestamp {start} is out of range")),
+        }
+        self
+    }
+    /// Set the end time of the request window from a Unix timestamp in seconds.
+    /// `build` fails if the timestamp is out of range.
+    pub fn end_timestamp(mut self, end: i64) -> Self {
+        self.mark_set("end");
+        match chrono::DateTime::from_timestamp(end, 0) {
+            Some(end) => self.end = Some(end.naive_utc()),
+            None => self.invalid(format!("End timestamp {end} is out of range")),
+        }
+        self
+    }
+    fn invalid(&mut self, error: String) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+    fn mark_set(&mut self, field: &'static str) {
+        if !self.strict {
+            return;
+        }
+        if self.set.contains(&field) {
+            self.invalid(format!("The window {field} was set more than once"));
+        } else {
+            self.set.push(field);
+        }
+    }
     /// Set the length of the request window
     pub fn length(mut self, length: WindowLength) -> Self {
+        self.mark_set("length");
         self.length = Some(length);
         self
     }
-    /// Build the window
-    pub fn build(self) -> Result<Window, String> {
-        if self.start.is_some() && self.end.is_some() {
-            if self.start.unwrap() > self.end.unwrap() {
-                return Err("Start time must be before end time".to_string());
+    /// Set whether the end time is included in the request window. Defaults to inclusive
+    pub fn bound(mut self, bound: Bound) -> Self {
+        self.mark_set("bound");
+        self.bound = bound;
+        self
+    }
+    /// Build a rolling series of overlapping windows for walk-forward testing.
+    /// The start and end set the whole range, the length sets the length of each window,
+    /// and `step` is how far each window starts after the previous one.
+    /// Only windows that fit entirely inside the range are returned, so the tail of the range
+    /// after the last full window is not covered, and a range shorter than the length gives no windows.
+    /// ```
+    /// use moabdb::window::*;
+    /// let windows = WindowBuilder::new()
+    ///     .start_timestamp(0)
+    ///     .end_timestamp(86400 * 10)
+    ///     .length(WindowLength::Days(5))
+    ///     .build_all(WindowLength::Days(3))
+    ///     .unwrap();
+    /// // Windows start on days 0 and 3, and one starting on day 6 would end on day 11
+    /// assert_eq!(windows.len(), 2);
+    /// assert_eq!(windows[1].start.and_utc().timestamp(), 86400 * 3);
+    /// assert_eq!(windows[1].end.and_utc().timestamp(), 86400 * 8);
+    /// ```
+    pub fn build_all(self, step: WindowLength) -> Result<Vec<Window>, String> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let (start, end, length) = match (self.start, self.end, self.length) {
+            (Some(start), Some(end), Some(length)) => (start, end, length),
+            _ => {
+                return Err(
+                    "Must provide start, end and length to build rolling windows".to_string(),
+                )
             }
-            return Ok(Window {
-                start: self.start.unwrap(),
-                end: self.end.unwrap(),
-            });
+        };
+        let (length, step) = match (length.duration(), step.duration()) {
+            (Some(length), Some(step)) => (length, step),
+            _ => return Err("Window is out of range".to_string()),
+        };
+        if step <= chrono::Duration::zero() || length <= chrono::Duration::zero() {
+            return Err("Window length and step must be positive".to_string());
         }
-        if self.start.is_some() && self.length.is_some() {
-            let start = self.start.unwrap();
-            let length = self.length.unwrap();
-            let end = match length {
-                WindowLength::Seconds(s) => start + chrono::Duration::seconds(s),
-                WindowLength::Minutes(m) => start + chrono::Duration::minutes(m),
-                WindowLength::Hours(h) => start + chrono::Duration::hours(h),
-                WindowLength::Days(d) => start + chrono::Duration::days(d),
-                WindowLength::Weeks(w) => start + chrono::Duration::weeks(w),
-                WindowLength::Months(m) => start + chrono::Duration::days(m * 30),
-                WindowLength::Years(y) => start + chrono::Duration::days(y * 365),
-            };
-            return Ok(Window { start, end });
-        }
-        if self.end.is_some() && self.length.is_some() {
-            let end = self.end.unwrap();
-            let length = self.length.unwrap();
-            let start = match length {
-                WindowLength::Seconds(s) => end - chrono::Duration::seconds(s),
-                WindowLength::Minutes(m) => end - chrono::Duration::minutes(m),
-                WindowLength::Hours(h) => end - chrono::Duration::hours(h),
-                WindowLength::Days(d) => end - chrono::Duration::days(d),
-                WindowLength::Weeks(w) => end - chrono::Duration::weeks(w),
-                WindowLength::Months(m) => end - chrono::Duration::days(m * 30),
-                WindowLength::Years(y) => end - chrono::Duration::days(y * 365),
-            };
-            return Ok(Window { start, end });
-        }
-        if self.length.is_some() {
-            // Get the current time
-            let now = chrono::Local::now().naive_local();
-            let length = self.length.unwrap();
-            let start = match length {
-                WindowLength::Seconds(s) => now - chrono::Duration::seconds(s),
-                WindowLength::Minutes(m) => now - chrono::Duration::minutes(m),
-                WindowLength::Hours(h) => now - chrono::Duration::hours(h),
-                WindowLength::Days(d) => now - chrono::Duration::days(d),
-                WindowLength::Weeks(w) => now - chrono::Duration::weeks(w),
-                WindowLength::Months(m) => now - chrono::Duration::days(m * 30),
-                WindowLength::Years(y) => now - chrono::Duration::days(y * 365),
+        if start > end {
+            return Err("Start time must be before end time".to_string());
+        }
+
+        let mut windows = Vec::new();
+        let mut window_start = start;
+        while let Some(window_end) = window_start.checked_add_signed(length) {
+            if window_end > end {
+                break;
+            }
+            windows.push(Window {
+                start: window_start,
+                end: window_end,
+                bound: self.bound,
+            });
+            window_start = match window_start.checked_add_signed(step) {
+                Some(next) => next,
+                None => break,
             };
-            return Ok(Window { start, end: now });
         }
+        Ok(windows)
+    }
+    /// Build the window
+    pub fn build(self) -> Result<Window, String> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let out_of_range = || "Window is out of range".to_string();
+        let (start, end) = match (self.start, self.end, self.length) {
+            (Some(start), Some(end), _) => {
+                if start > end {
+                    return Err("Start time must be before end time".to_string());
+                }
+                (start, end)
+            }
+            (Some(start), None, Some(length)) => {
+                let end = length
+                    .duration()
+                    .and_then(|length| start.checked_add_signed(length))
+                    .ok_or_else(out_of_range)?;
+                (start, end)
+            }
+            (None, Some(end), Some(length)) => {
+                let start = length
+                    .duration()
+                    .and_then(|length| end.checked_sub_signed(length))
+                    .ok_or_else(out_of_range)?;
+                (start, end)
+            }
+            (None, None, Some(length)) => {
+                // Get the current time
+                let now = chrono::Local::now().naive_local();
+                let start = length
+                    .duration()
+                    .and_then(|length| now.checked_sub_signed(length))
+                    .ok_or_else(out_of_range)?;
+                (start, now)
+            }
+            _ => return Err("Must provide either start and end or start and length".to_string()),
+        };
 
-        Err("Must provide either start and end or start and length".to_string())
+        Ok(Window {
+            start,
+            end,
+            bound: self.bound,
+        })
     }
 }
 
@@ -169,3 +959,401 @@ impl Default for WindowBuilder {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamp(secs: i64) -> NaiveDateTime {
+        chrono::DateTime::from_timestamp(secs, 0)
+            .unwrap()
+            .naive_utc()
+    }
+
+    fn window(bound: Bound) -> Window {
+        WindowBuilder::new()
+            .start(timestamp(0))
+            .end(timestamp(86400))
+            .bound(bound)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn inclusive_end_is_sent_as_is() {
+        assert_eq!(window(Bound::Inclusive).request_timestamps(), (0, 86400));
+    }
+
+    #[test]
+    fn exclusive_end_drops_the_boundary_second() {
+        assert_eq!(window(Bound::Exclusive).request_timestamps(), (0, 86399));
+    }
+
+    fn iso8601(s: &str) -> Option<chrono::Duration> {
+        WindowLength::from_iso8601(s).ok()?.duration()
+    }
+
+    #[test]
+    fn iso8601_single_components() {
+        assert!(matches!(
+            WindowLength::from_iso8601("P2Y"),
+            Ok(WindowLength::Years(2))
+        ));
+        assert!(matches!(
+            WindowLength::from_iso8601("P1W"),
+            Ok(WindowLength::Weeks(1))
+        ));
+        assert!(matches!(
+            WindowLength::from_iso8601("PT30S"),
+            Ok(WindowLength::Seconds(30))
+        ));
+    }
+
+    #[test]
+    fn iso8601_mixed_components() {
+        assert!(matches!(
+            WindowLength::from_iso8601("P1Y6M"),
+            Ok(WindowLength::Months(18))
+        ));
+        assert_eq!(iso8601("P1M1W"), chrono::Duration::try_days(37));
+        assert_eq!(iso8601("P1Y1D"), chrono::Duration::try_days(366));
+        assert_eq!(iso8601("PT1H30M"), chrono::Duration::try_minutes(90));
+    }
+
+    #[test]
+    fn iso8601_rejects_malformed() {
+        for s in [
+            "", "P", "PT", "3M", "P1H", "PT1D", "P1D1Y", "P1M1M", "P1DT", "PM",
+        ] {
+            assert!(WindowLength::from_iso8601(s).is_err(), "{s}");
+        }
+        assert!(WindowLength::from_iso8601("P99999999999999999999D").is_err());
+    }
+
+    #[test]
+    fn strict_builder_rejects_double_sets() {
+        let start_twice = WindowBuilder::strict()
+            .start(timestamp(0))
+            .start_timestamp(60)
+            .end(timestamp(86400))
+            .build();
+        assert!(start_twice.is_err());
+        let bound_twice = WindowBuilder::strict()
+            .start(timestamp(0))
+            .end(timestamp(86400))
+            .bound(Bound::Exclusive)
+            .bound(Bound::Inclusive)
+            .build();
+        assert!(bound_twice.is_err());
+        let once = WindowBuilder::strict()
+            .start(timestamp(0))
+            .end(timestamp(86400))
+            .bound(Bound::Exclusive)
+            .build();
+        assert!(once.is_ok());
+    }
+
+    #[test]
+    fn lenient_builder_keeps_the_last_set() {
+        let window = WindowBuilder::new()
+            .start(timestamp(0))
+            .start(timestamp(60))
+            .end(timestamp(86400))
+            .build()
+            .unwrap();
+        assert_eq!(window.start, timestamp(60));
+    }
+
+    fn session_open(year: i32, month: u32, day: u32) -> u32 {
+        let date = NaiveDate::from_ymd_opt(year, month, day).unwrap();
+        chrono::Timelike::hour(&Window::session(date, Market::Nasdaq).start)
+    }
+
+    #[test]
+    fn session_follows_daylight_saving_time() {
+        assert_eq!(session_open(2023, 1, 9), 14);
+        // 2023 daylight time ran from March 12 to November 5
+        assert_eq!(session_open(2023, 3, 10), 14);
+        assert_eq!(session_open(2023, 3, 13), 13);
+        assert_eq!(session_open(2023, 11, 3), 13);
+        assert_eq!(session_open(2023, 11, 6), 14);
+        // 2006 daylight time ran from April 2 to October 29
+        assert_eq!(session_open(2006, 3, 31), 14);
+        assert_eq!(session_open(2006, 4, 3), 13);
+        assert_eq!(session_open(2006, 10, 30), 14);
+    }
+
+    #[test]
+    fn estimate_rows_counts_weekdays_and_session_minutes() {
+        // Monday July 3 to Monday July 10, 2023
+        let week = Window {
+            start: new_york(7, 3, 0, 0, 0),
+            end: new_york(7, 10, 0, 0, 0),
+            bound: Bound::Exclusive,
+        };
+        assert_eq!(week.estimate_rows(false, Market::Nyse), 5);
+        assert_eq!(week.estimate_rows(true, Market::Nyse), 5 * 390);
+        // 9:30:30 to 9:35 inclusive holds the bars at 9:31 through 9:35
+        let minutes = Window {
+            start: new_york(7, 3, 9, 30, 30),
+            end: new_york(7, 3, 9, 35, 0),
+            bound: Bound::Inclusive,
+        };
+        assert_eq!(minutes.estimate_rows(true, Market::Nyse), 5);
+        let exclusive = Window {
+            bound: Bound::Exclusive,
+            ..minutes
+        };
+        assert_eq!(exclusive.estimate_rows(true, Market::Nyse), 4);
+        // After the close in winter, when New York is five hours behind UTC
+        let evening = Window {
+            start: new_york(1, 9, 16, 0, 0),
+            end: new_york(1, 9, 23, 0, 0),
+            bound: Bound::Inclusive,
+        };
+        assert_eq!(evening.estimate_rows(true, Market::Nyse), 0);
+    }
+
+    #[test]
+    fn sessions_follow_daylight_saving_time() {
+        // 8:00 New York time is 13:00 UTC in winter and 12:00 UTC in summer
+        let winter = new_york(1, 9, 8, 0, 0);
+        let summer = new_york(7, 3, 8, 0, 0);
+        assert_eq!(chrono::Timelike::hour(&winter), 13);
+        assert_eq!(chrono::Timelike::hour(&summer), 12);
+        for utc in [winter, summer] {
+            assert!(Session::PreMarket.contains(utc, Market::Nyse));
+            assert!(!Session::Regular.contains(utc, Market::Nyse));
+        }
+        // 13:00 UTC in summer is 9:00, still before the open
+        let early = summer + chrono::Duration::hours(1);
+        assert!(Session::PreMarket.contains(early, Market::Nyse));
+    }
+
+    #[test]
+    fn sessions_include_their_start_only() {
+        let open = new_york(7, 3, 9, 30, 0);
+        let close = new_york(7, 3, 16, 0, 0);
+        let late = new_york(7, 3, 20, 0, 0);
+        assert!(Session::Regular.contains(open, Market::Nyse));
+        assert!(!Session::PreMarket.contains(open, Market::Nyse));
+        assert!(Session::AfterHours.contains(close, Market::Nyse));
+        assert!(!Session::Regular.contains(close, Market::Nyse));
+        assert!(!Session::AfterHours.contains(late, Market::Nyse));
+        assert!(Session::All.contains(late, Market::Nyse));
+    }
+
+    fn span(start: i64, end: i64, bound: Bound) -> Window {
+        Window {
+            start: timestamp(start),
+            end: timestamp(end),
+            bound,
+        }
+    }
+
+    #[test]
+    fn merge_keeps_the_bound_of_the_later_end() {
+        let a = span(0, 100, Bound::Inclusive);
+        let b = span(50, 200, Bound::Exclusive);
+        assert_eq!(a.merge(b), Some(span(0, 200, Bound::Exclusive)));
+        assert_eq!(b.merge(a), Some(span(0, 200, Bound::Exclusive)));
+        let c = span(50, 100, Bound::Exclusive);
+        assert_eq!(a.merge(c), Some(span(0, 100, Bound::Inclusive)));
+    }
+
+    #[test]
+    fn adjacent_windows_merge_without_overlapping() {
+        let a = span(0, 100, Bound::Exclusive);
+        let b = span(100, 200, Bound::Inclusive);
+        assert!(!a.overlaps(&b));
+        assert_eq!(a.merge(b), Some(span(0, 200, Bound::Inclusive)));
+    }
+
+    #[test]
+    fn coalesce_handles_nested_windows() {
+        let windows = vec![
+            span(0, 1000, Bound::Inclusive),
+            span(100, 200, Bound::Inclusive),
+            span(2000, 3000, Bound::Inclusive),
+        ];
+        assert_eq!(
+            coalesce(windows),
+            vec![
+                span(0, 1000, Bound::Inclusive),
+                span(2000, 3000, Bound::Inclusive)
+            ]
+        );
+        assert!(coalesce(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn missing_ranges_without_a_cache_is_the_whole_window() {
+        let requested = span(0, 100, Bound::Inclusive);
+        assert_eq!(missing_ranges(requested, &[]), vec![requested]);
+        // Windows outside the request don't count
+        let outside = [
+            span(200, 300, Bound::Inclusive),
+            span(-50, -1, Bound::Inclusive),
+        ];
+        assert_eq!(missing_ranges(requested, &outside), vec![requested]);
+        let exclusive = span(0, 100, Bound::Exclusive);
+        assert_eq!(
+            missing_ranges(exclusive, &[span(100, 200, Bound::Inclusive)]),
+            vec![exclusive]
+        );
+    }
+
+    #[test]
+    fn missing_ranges_is_empty_when_covered() {
+        let requested = span(0, 100, Bound::Inclusive);
+        assert!(missing_ranges(requested, &[requested]).is_empty());
+        assert!(missing_ranges(requested, &[span(-100, 200, Bound::Exclusive)]).is_empty());
+        // Pieces that overlap, touch or are a second apart cover it together, in any order
+        let pieces = [
+            span(60, 100, Bound::Inclusive),
+            span(0, 30, Bound::Inclusive),
+            span(31, 50, Bound::Exclusive),
+            span(40, 60, Bound::Exclusive),
+        ];
+        assert!(missing_ranges(requested, &pieces).is_empty());
+        // An exclusive request doesn't need its end
+        let exclusive = span(0, 100, Bound::Exclusive);
+        assert!(missing_ranges(exclusive, &[span(0, 100, Bound::Exclusive)]).is_empty());
+    }
+
+    #[test]
+    fn missing_ranges_finds_every_gap() {
+        let requested = span(0, 1000, Bound::Inclusive);
+        let have = [
+            span(800, 900, Bound::Inclusive),
+            span(100, 200, Bound::Exclusive),
+            span(300, 400, Bound::Inclusive),
+        ];
+        assert_eq!(
+            missing_ranges(requested, &have),
+            vec![
+                span(0, 100, Bound::Exclusive),
+                span(200, 300, Bound::Exclusive),
+                span(401, 800, Bound::Exclusive),
+                span(901, 1000, Bound::Inclusive),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_ranges_at_the_edges() {
+        let requested = span(0, 100, Bound::Inclusive);
+        // Covered up to, but not including, the end leaves just the end
+        assert_eq!(
+            missing_ranges(requested, &[span(0, 100, Bound::Exclusive)]),
+            vec![span(100, 100, Bound::Inclusive)]
+        );
+        // A cached window starting at the end covers it
+        let have = [
+            span(0, 50, Bound::Inclusive),
+            span(100, 200, Bound::Inclusive),
+        ];
+        assert_eq!(
+            missing_ranges(requested, &have),
+            vec![span(51, 100, Bound::Exclusive)]
+        );
+        // A cached window ending at the start leaves the start to fetch unless it includes it
+        assert_eq!(
+            missing_ranges(requested, &[span(-100, 0, Bound::Exclusive)]),
+            vec![requested]
+        );
+        assert_eq!(
+            missing_ranges(requested, &[span(-100, 0, Bound::Inclusive)]),
+            vec![span(1, 100, Bound::Inclusive)]
+        );
+    }
+
+    fn new_york(month: u32, day: u32, hour: u32, min: u32, sec: u32) -> NaiveDateTime {
+        let date = NaiveDate::from_ymd_opt(2023, month, day).unwrap();
+        let offset = chrono::Duration::hours(Market::Nyse.utc_offset(date));
+        date.and_hms_opt(hour, min, sec).unwrap() - offset
+    }
+
+    fn aligned(
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        bound: Bound,
+        granularity: WindowLength,
+    ) -> Window {
+        Window { start, end, bound }.align_to(granularity, Market::Nyse)
+    }
+
+    #[test]
+    fn align_to_anchors_intraday_bars_at_the_open() {
+        // Hourly bars start at 9:30 local time, in both standard and daylight time
+        for month in [1, 7] {
+            let window = aligned(
+                new_york(month, 10, 10, 0, 0),
+                new_york(month, 10, 12, 0, 0),
+                Bound::Inclusive,
+                WindowLength::Hours(1),
+            );
+            assert_eq!(window.start, new_york(month, 10, 9, 30, 0));
+            assert_eq!(window.end, new_york(month, 10, 12, 30, 0));
+        }
+    }
+
+    #[test]
+    fn align_to_keeps_an_exclusive_end_on_a_boundary() {
+        let start = new_york(7, 10, 9, 31, 42);
+        let end = new_york(7, 10, 9, 35, 0);
+        let exclusive = aligned(start, end, Bound::Exclusive, WindowLength::Minutes(1));
+        assert_eq!(
+            (exclusive.start, exclusive.end),
+            (new_york(7, 10, 9, 31, 0), end)
+        );
+        // An inclusive end includes the bar starting there
+        let inclusive = aligned(start, end, Bound::Inclusive, WindowLength::Minutes(1));
+        assert_eq!(inclusive.end, new_york(7, 10, 9, 36, 0));
+        // Aligning is idempotent
+        assert_eq!(
+            exclusive.align_to(WindowLength::Minutes(1), Market::Nyse),
+            exclusive
+        );
+    }
+
+    #[test]
+    fn align_to_uses_local_midnight_and_calendar_months() {
+        let start = new_york(7, 10, 9, 31, 0);
+        let end = new_york(8, 15, 16, 0, 0);
+        let days = aligned(start, end, Bound::Inclusive, WindowLength::Days(1));
+        assert_eq!(
+            (days.start, days.end),
+            (new_york(7, 10, 0, 0, 0), new_york(8, 16, 0, 0, 0))
+        );
+        // July 10, 2023 was a Monday
+        let weeks = aligned(start, end, Bound::Inclusive, WindowLength::Weeks(1));
+        assert_eq!(
+            (weeks.start, weeks.end),
+            (new_york(7, 10, 0, 0, 0), new_york(8, 21, 0, 0, 0))
+        );
+        let months = aligned(start, end, Bound::Inclusive, WindowLength::Months(3));
+        assert_eq!(
+            (months.start, months.end),
+            (new_york(7, 1, 0, 0, 0), new_york(10, 1, 0, 0, 0))
+        );
+        let unchanged = Window {
+            start,
+            end,
+            bound: Bound::Inclusive,
+        };
+        assert_eq!(
+            unchanged.align_to(WindowLength::Days(0), Market::Nyse),
+            unchanged
+        );
+    }
+
+    #[test]
+    fn default_bound_is_inclusive() {
+        let window = WindowBuilder::new()
+            .length(WindowLength::Days(1))
+            .build()
+            .unwrap();
+        assert_eq!(window.bound, Bound::Inclusive);
+    }
+}